@@ -0,0 +1,108 @@
+use std::cmp;
+use std::future::Future;
+use std::io;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use tokio::time;
+
+const MIN_BACKOFF: Duration = Duration::from_millis(5);
+const MAX_BACKOFF: Duration = Duration::from_secs(1);
+
+static ACCEPT_FAILURES: AtomicU64 = AtomicU64::new(0);
+
+// number of failed accepts since startup
+pub fn accept_failures() -> u64 {
+    ACCEPT_FAILURES.load(Ordering::Relaxed)
+}
+
+// Transient errors are logged and retried with exponential backoff.
+// Anything else means the listener itself is broken.
+pub async fn accept<F, Fut, T>(accept_fn: F) -> Result<T, String>
+where
+    F: Fn() -> Fut,
+    Fut: Future<Output = io::Result<T>>,
+{
+    let mut backoff = MIN_BACKOFF;
+
+    loop {
+        let e = match accept_fn().await {
+            Ok(accepted) => return Ok(accepted),
+            Err(e) => e,
+        };
+
+        let failures = ACCEPT_FAILURES.fetch_add(1, Ordering::Relaxed) + 1;
+        if !is_transient(&e) {
+            return Err(e.to_string());
+        }
+
+        println!(
+            "file_server: accept failed, retrying in {}ms ({} failures): {}",
+            backoff.as_millis(),
+            failures,
+            e
+        );
+
+        time::sleep(backoff).await;
+        backoff = cmp::min(backoff * 2, MAX_BACKOFF);
+    }
+}
+
+// file descriptor exhaustion clears up as connections close,
+// aborted connections only affect a single client
+fn is_transient(e: &io::Error) -> bool {
+    match e.raw_os_error() {
+        Some(libc::EMFILE | libc::ENFILE | libc::ENOBUFS | libc::ENOMEM) => true,
+        Some(libc::ECONNABORTED | libc::ECONNRESET | libc::EINTR) => true,
+        _ => matches!(
+            e.kind(),
+            io::ErrorKind::ConnectionAborted
+                | io::ErrorKind::ConnectionReset
+                | io::ErrorKind::Interrupted
+        ),
+    }
+}
+
+// The soft limit is raised as far as the hard limit allows. Raising the
+// hard limit requires privileges, failing to do so is only logged.
+pub fn raise_open_files_limit(max_open_files: u64) -> Result<(), String> {
+    let mut limit = libc::rlimit {
+        rlim_cur: 0,
+        rlim_max: 0,
+    };
+
+    // SAFETY: limit is a valid rlimit the call writes into
+    if -1 == unsafe { libc::getrlimit(libc::RLIMIT_NOFILE, &mut limit) } {
+        return Err(io::Error::last_os_error().to_string());
+    }
+
+    let target = max_open_files as libc::rlim_t;
+    if target <= limit.rlim_cur {
+        return Ok(());
+    }
+
+    let raised = libc::rlimit {
+        rlim_cur: target,
+        rlim_max: cmp::max(target, limit.rlim_max),
+    };
+    // SAFETY: raised is a valid rlimit that is only read
+    if 0 == unsafe { libc::setrlimit(libc::RLIMIT_NOFILE, &raised) } {
+        println!("file_server: open files limit {}", target);
+        return Ok(());
+    }
+
+    let clamped = libc::rlimit {
+        rlim_cur: cmp::min(target, limit.rlim_max),
+        rlim_max: limit.rlim_max,
+    };
+    // SAFETY: clamped is a valid rlimit that is only read
+    if -1 == unsafe { libc::setrlimit(libc::RLIMIT_NOFILE, &clamped) } {
+        return Err(io::Error::last_os_error().to_string());
+    }
+
+    println!(
+        "file_server: open files limit {} (hard limit {} is below max_open_files {})",
+        clamped.rlim_cur, limit.rlim_max, target
+    );
+
+    Ok(())
+}
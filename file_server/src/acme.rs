@@ -0,0 +1,612 @@
+use rcgen::{date_time_ymd, CertificateParams, CustomExtension, DistinguishedName, KeyPair};
+use ring::digest::{digest, SHA256};
+use rustls::crypto::ring::sign::any_supported_type;
+use rustls::pki_types::PrivateKeyDer;
+use rustls::sign::CertifiedKey;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tokio::fs;
+use tokio::sync::Notify;
+use tokio::time;
+
+use crate::acme_client::AcmeClient;
+use crate::config::{AcmeCertificate, AcmeConfig, TlsCertificate, TlsConfig};
+use crate::tls::{get_time_to_expiration, write_key};
+
+// https://www.rfc-editor.org/rfc/rfc8737#section-6.2
+pub const ACME_TLS_ALPN: &[u8] = b"acme-tls/1";
+
+const HTTP_01: &str = "http-01";
+const TLS_ALPN_01: &str = "tls-alpn-01";
+
+const DAY: u64 = 24 * 60 * 60;
+const RENEW_BEFORE_DAYS: u64 = 30;
+const CHECK_INTERVAL: Duration = Duration::from_secs(12 * 60 * 60);
+const RETRY_INTERVAL: Duration = Duration::from_secs(60 * 60);
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+const MAX_POLLS: u32 = 60;
+
+enum ChallengeType {
+    // files are written under a directory served by the redirect listener
+    Http01(PathBuf),
+    // certificates are served to clients offering the acme-tls/1 protocol
+    TlsAlpn01,
+}
+
+impl ChallengeType {
+    fn name(&self) -> &str {
+        match self {
+            ChallengeType::Http01(_) => HTTP_01,
+            ChallengeType::TlsAlpn01 => TLS_ALPN_01,
+        }
+    }
+}
+
+// pending tls-alpn-01 challenges by domain
+static TLS_ALPN_CERTIFIED_KEYS: RwLock<BTreeMap<String, Arc<CertifiedKey>>> =
+    RwLock::new(BTreeMap::new());
+
+pub fn get_tls_alpn_certified_key(server_name: &str) -> Option<Arc<CertifiedKey>> {
+    match TLS_ALPN_CERTIFIED_KEYS.read() {
+        Ok(certified_keys) => certified_keys
+            .get(&server_name.to_ascii_lowercase())
+            .cloned(),
+        _ => None,
+    }
+}
+
+// ACME certificates are served like any other certificate. Missing ones get
+// an expired placeholder that is replaced by the first order.
+pub async fn add_acme_certificates(tls: &mut TlsConfig) -> Result<(), String> {
+    let acme = match &tls.acme {
+        Some(acme) => acme,
+        _ => return Ok(()),
+    };
+
+    // validation servers never present a client certificate
+    let client_auth_required = match &tls.client_auth {
+        Some(client_auth) => client_auth.required.unwrap_or(false),
+        _ => false,
+    };
+    if client_auth_required && Some(TLS_ALPN_01) == acme.challenge.as_deref() {
+        return Err("acme tls-alpn-01 challenges require client_auth to be optional".to_string());
+    }
+
+    if let Err(e) = fs::create_dir_all(&acme.storage).await {
+        return Err(format!("{}: {}", acme.storage.display(), e));
+    }
+
+    for acme_certificate in &acme.certificates {
+        let certificate = get_certificate_paths(&acme.storage, acme_certificate)?;
+        if !fs::try_exists(&certificate.cert).await.unwrap_or(false) {
+            create_placeholder(&certificate, &acme_certificate.domains).await?;
+        }
+
+        tls.certificates.push(certificate);
+    }
+
+    Ok(())
+}
+
+// Certificates are ordered when they are missing or about to expire.
+// Reloads are triggered through the reload notifier.
+pub fn spawn_acme(
+    acme: AcmeConfig,
+    challenge_directory: Option<PathBuf>,
+    reload: Arc<Notify>,
+) -> Result<(), String> {
+    let challenge_type = match (acme.challenge.as_deref(), challenge_directory) {
+        (None | Some(HTTP_01), Some(challenge_dir)) => ChallengeType::Http01(challenge_dir),
+        (None | Some(HTTP_01), _) => {
+            return Err("acme http-01 challenges require http_redirect".to_string())
+        }
+        (Some(TLS_ALPN_01), _) => ChallengeType::TlsAlpn01,
+        (Some(challenge), _) => return Err(format!("unsupported acme challenge: {}", challenge)),
+    };
+
+    tokio::spawn(async move {
+        loop {
+            // failed orders are retried sooner
+            if renew_certificates(&acme, &challenge_type, &reload).await {
+                time::sleep(CHECK_INTERVAL).await;
+            } else {
+                time::sleep(RETRY_INTERVAL).await;
+            }
+        }
+    });
+
+    Ok(())
+}
+
+// returns false if any certificate could not be renewed
+async fn renew_certificates(
+    acme: &AcmeConfig,
+    challenge_type: &ChallengeType,
+    reload: &Notify,
+) -> bool {
+    let renew_before =
+        Duration::from_secs(acme.renew_before_days.unwrap_or(RENEW_BEFORE_DAYS) * DAY);
+
+    let mut client: Option<AcmeClient> = None;
+    let mut renewed_all = true;
+
+    for acme_certificate in &acme.certificates {
+        let certificate = match get_certificate_paths(&acme.storage, acme_certificate) {
+            Ok(certificate) => certificate,
+            _ => continue,
+        };
+
+        if let Some(remaining) = get_time_to_expiration(&certificate.cert).await {
+            if remaining > renew_before {
+                continue;
+            }
+        }
+
+        // accounts are only looked up when a certificate is due
+        let acme_client = match &mut client {
+            Some(acme_client) => acme_client,
+            _ => match get_account(acme).await {
+                Ok(acme_client) => client.insert(acme_client),
+                Err(e) => {
+                    println!("file_server: acme account failed: {}", e);
+                    return false;
+                }
+            },
+        };
+
+        let domains = acme_certificate.domains.join(", ");
+        match order_certificate(acme_client, challenge_type, acme_certificate, &certificate).await {
+            Ok(_) => {
+                println!("file_server: acme certificate issued for {}", domains);
+                reload.notify_one();
+            }
+            Err(e) => {
+                println!(
+                    "file_server: acme certificate failed for {}: {}",
+                    domains, e
+                );
+                renewed_all = false;
+            }
+        }
+    }
+
+    renewed_all
+}
+
+// the account key is created once and reused
+async fn get_account(acme: &AcmeConfig) -> Result<AcmeClient, String> {
+    let account_key_path = acme.storage.join("account.key");
+
+    let account_key = match fs::read_to_string(&account_key_path).await {
+        Ok(pem) => match KeyPair::from_pem(&pem) {
+            Ok(kp) => kp,
+            Err(e) => return Err(format!("{}: {}", account_key_path.display(), e)),
+        },
+        _ => {
+            let key_pair = match KeyPair::generate() {
+                Ok(kp) => kp,
+                Err(e) => return Err(e.to_string()),
+            };
+            write_key(&account_key_path, &key_pair.serialize_pem()).await?;
+            key_pair
+        }
+    };
+
+    let mut client =
+        AcmeClient::new(&acme.directory_url, &acme.ca, account_key.serialized_der()).await?;
+
+    let contact = acme.contact.clone().unwrap_or_default();
+    client.register(&contact).await?;
+
+    Ok(client)
+}
+
+// https://www.rfc-editor.org/rfc/rfc8555#section-7.4
+async fn order_certificate(
+    client: &mut AcmeClient,
+    challenge_type: &ChallengeType,
+    acme_certificate: &AcmeCertificate,
+    certificate: &TlsCertificate,
+) -> Result<(), String> {
+    let (order_url, order) = client.new_order(&acme_certificate.domains).await?;
+
+    for authorization_url in &order.authorizations {
+        authorize(client, challenge_type, authorization_url).await?;
+    }
+
+    // the csr only carries the domains
+    let key_pair = match KeyPair::generate() {
+        Ok(kp) => kp,
+        Err(e) => return Err(e.to_string()),
+    };
+
+    let mut params = match CertificateParams::new(acme_certificate.domains.clone()) {
+        Ok(params) => params,
+        Err(e) => return Err(e.to_string()),
+    };
+    params.distinguished_name = DistinguishedName::new();
+
+    let csr = match params.serialize_request(&key_pair) {
+        Ok(csr) => csr,
+        Err(e) => return Err(e.to_string()),
+    };
+
+    let mut order = client.finalize(&order.finalize, csr.der()).await?;
+
+    let mut polls = 0;
+    while "valid" != order.status {
+        if "invalid" == order.status || MAX_POLLS < polls {
+            return Err(format!("{}: order is {}", order_url, order.status));
+        }
+
+        polls += 1;
+        time::sleep(POLL_INTERVAL).await;
+        order = client.get_order(&order_url).await?;
+    }
+
+    let certificate_url = match order.certificate {
+        Some(url) => url,
+        _ => return Err(format!("{}: no certificate", order_url)),
+    };
+
+    let cert_pem = client.get_certificate(&certificate_url).await?;
+
+    write_certificate(certificate, &cert_pem, &key_pair.serialize_pem()).await
+}
+
+async fn authorize(
+    client: &mut AcmeClient,
+    challenge_type: &ChallengeType,
+    authorization_url: &str,
+) -> Result<(), String> {
+    let authorization = client.get_authorization(authorization_url).await?;
+
+    // authorizations are reused for a while after they succeed
+    if "valid" == authorization.status {
+        return Ok(());
+    }
+
+    let challenge = authorization
+        .challenges
+        .iter()
+        .find(|challenge| challenge_type.name() == challenge.r#type);
+
+    let (challenge_url, token) = match challenge {
+        Some(challenge) => match &challenge.token {
+            Some(token) => (challenge.url.clone(), token.clone()),
+            _ => return Err(format!("{}: challenge without token", challenge.url)),
+        },
+        _ => {
+            return Err(format!(
+                "{}: no {} challenge",
+                authorization_url,
+                challenge_type.name()
+            ))
+        }
+    };
+
+    let domain = authorization.identifier.value.to_ascii_lowercase();
+    let key_authorization = client.get_key_authorization(&token);
+
+    publish_challenge(challenge_type, &domain, &token, &key_authorization).await?;
+    let result = validate_challenge(client, authorization_url, &challenge_url).await;
+    unpublish_challenge(challenge_type, &domain, &token).await;
+
+    result
+}
+
+async fn validate_challenge(
+    client: &mut AcmeClient,
+    authorization_url: &str,
+    challenge_url: &str,
+) -> Result<(), String> {
+    client.respond_to_challenge(challenge_url).await?;
+
+    for _ in 0..MAX_POLLS {
+        time::sleep(POLL_INTERVAL).await;
+
+        let authorization = client.get_authorization(authorization_url).await?;
+        match authorization.status.as_str() {
+            "valid" => return Ok(()),
+            "pending" | "processing" => continue,
+            status => {
+                return Err(format!(
+                    "{}: authorization is {}",
+                    authorization_url, status
+                ))
+            }
+        }
+    }
+
+    Err(format!("{}: authorization timed out", authorization_url))
+}
+
+async fn publish_challenge(
+    challenge_type: &ChallengeType,
+    domain: &str,
+    token: &str,
+    key_authorization: &str,
+) -> Result<(), String> {
+    match challenge_type {
+        ChallengeType::Http01(challenge_dir) => {
+            let challenge_path = get_http_challenge_path(challenge_dir, token)?;
+            if let Some(parent) = challenge_path.parent() {
+                if let Err(e) = fs::create_dir_all(parent).await {
+                    return Err(format!("{}: {}", parent.display(), e));
+                }
+            }
+
+            match fs::write(&challenge_path, key_authorization).await {
+                Ok(_) => Ok(()),
+                Err(e) => Err(format!("{}: {}", challenge_path.display(), e)),
+            }
+        }
+        ChallengeType::TlsAlpn01 => {
+            let certified_key = create_tls_alpn_certified_key(domain, key_authorization)?;
+            match TLS_ALPN_CERTIFIED_KEYS.write() {
+                Ok(mut certified_keys) => {
+                    certified_keys.insert(domain.to_string(), certified_key);
+                    Ok(())
+                }
+                _ => Err("tls-alpn-01 challenges lock poisoned".to_string()),
+            }
+        }
+    }
+}
+
+async fn unpublish_challenge(challenge_type: &ChallengeType, domain: &str, token: &str) {
+    match challenge_type {
+        ChallengeType::Http01(challenge_dir) => {
+            if let Ok(challenge_path) = get_http_challenge_path(challenge_dir, token) {
+                let _ = fs::remove_file(challenge_path).await;
+            }
+        }
+        ChallengeType::TlsAlpn01 => {
+            if let Ok(mut certified_keys) = TLS_ALPN_CERTIFIED_KEYS.write() {
+                certified_keys.remove(domain);
+            }
+        }
+    }
+}
+
+// tokens are base64url so they can't leave the challenge directory
+fn get_http_challenge_path(challenge_dir: &Path, token: &str) -> Result<PathBuf, String> {
+    let is_base64url = token
+        .bytes()
+        .all(|b| b.is_ascii_alphanumeric() || b'-' == b || b'_' == b);
+    if token.is_empty() || !is_base64url {
+        return Err(format!("invalid acme token: {}", token));
+    }
+
+    Ok(challenge_dir.join(".well-known/acme-challenge").join(token))
+}
+
+// https://www.rfc-editor.org/rfc/rfc8737#section-3
+fn create_tls_alpn_certified_key(
+    domain: &str,
+    key_authorization: &str,
+) -> Result<Arc<CertifiedKey>, String> {
+    let mut params = match CertificateParams::new(vec![domain.to_string()]) {
+        Ok(params) => params,
+        Err(e) => return Err(e.to_string()),
+    };
+
+    let key_authorization_hash = digest(&SHA256, key_authorization.as_bytes());
+    params.custom_extensions = vec![CustomExtension::new_acme_identifier(
+        key_authorization_hash.as_ref(),
+    )];
+
+    let key_pair = match KeyPair::generate() {
+        Ok(kp) => kp,
+        Err(e) => return Err(e.to_string()),
+    };
+
+    let cert = match params.self_signed(&key_pair) {
+        Ok(cert) => cert,
+        Err(e) => return Err(e.to_string()),
+    };
+
+    let key = PrivateKeyDer::Pkcs8(key_pair.serialize_der().into());
+    let signing_key = match any_supported_type(&key) {
+        Ok(sk) => sk,
+        Err(e) => return Err(e.to_string()),
+    };
+
+    Ok(Arc::new(CertifiedKey::new(
+        vec![cert.der().clone()],
+        signing_key,
+    )))
+}
+
+// files are named after the first domain
+fn get_certificate_paths(
+    storage: &Path,
+    acme_certificate: &AcmeCertificate,
+) -> Result<TlsCertificate, String> {
+    let domain = match acme_certificate.domains.first() {
+        Some(domain) => domain.to_ascii_lowercase(),
+        _ => return Err("acme certificate without domains".to_string()),
+    };
+
+    if domain.contains(['/', '*']) || domain.starts_with(".") {
+        return Err(format!("invalid acme domain: {}", domain));
+    }
+
+    Ok(TlsCertificate {
+        cert: storage.join(format!("{}.pem", domain)),
+        key: storage.join(format!("{}.key", domain)),
+    })
+}
+
+// expired from the start so it is replaced right away
+async fn create_placeholder(
+    certificate: &TlsCertificate,
+    domains: &[String],
+) -> Result<(), String> {
+    let mut params = match CertificateParams::new(domains.to_vec()) {
+        Ok(params) => params,
+        Err(e) => return Err(e.to_string()),
+    };
+    params.not_before = date_time_ymd(1975, 1, 1);
+    params.not_after = date_time_ymd(1975, 1, 1);
+
+    let key_pair = match KeyPair::generate() {
+        Ok(kp) => kp,
+        Err(e) => return Err(e.to_string()),
+    };
+
+    let cert = match params.self_signed(&key_pair) {
+        Ok(cert) => cert,
+        Err(e) => return Err(e.to_string()),
+    };
+
+    write_certificate(certificate, &cert.pem(), &key_pair.serialize_pem()).await
+}
+
+// Files are replaced by renaming so a reload never reads a partial file.
+// The key comes first, a reload in between fails and keeps the old pair.
+async fn write_certificate(
+    certificate: &TlsCertificate,
+    cert_pem: &str,
+    key_pem: &str,
+) -> Result<(), String> {
+    let key_tmp = certificate.key.with_extension("key.tmp");
+    let cert_tmp = certificate.cert.with_extension("pem.tmp");
+
+    write_key(&key_tmp, key_pem).await?;
+    if let Err(e) = fs::write(&cert_tmp, cert_pem).await {
+        return Err(format!("{}: {}", cert_tmp.display(), e));
+    }
+
+    if let Err(e) = fs::rename(&key_tmp, &certificate.key).await {
+        return Err(format!("{}: {}", certificate.key.display(), e));
+    }
+
+    match fs::rename(&cert_tmp, &certificate.cert).await {
+        Ok(_) => Ok(()),
+        Err(e) => Err(format!("{}: {}", certificate.cert.display(), e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::process;
+    use tokio::net::TcpListener;
+    use tokio_util::sync::CancellationToken;
+    use tokio_util::task::TaskTracker;
+
+    use crate::service::Svc;
+    use crate::{get_challenge_requirements, serve_listener};
+
+    fn get_acme_certificate(domains: &[&str]) -> AcmeCertificate {
+        AcmeCertificate {
+            domains: domains.iter().map(|domain| domain.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn http_challenge_path_is_under_well_known() {
+        let challenge_path = get_http_challenge_path(Path::new("/srv/www"), "LoqXcYV8q5ONbJQ-x_2z");
+
+        assert_eq!(
+            Ok(PathBuf::from(
+                "/srv/www/.well-known/acme-challenge/LoqXcYV8q5ONbJQ-x_2z"
+            )),
+            challenge_path
+        );
+    }
+
+    #[test]
+    fn http_challenge_path_refuses_tokens_that_leave_the_directory() {
+        for token in ["", "..", "../index.html", "a/b", "a.b", "a%2Fb"] {
+            assert!(get_http_challenge_path(Path::new("/srv/www"), token).is_err());
+        }
+    }
+
+    #[test]
+    fn certificate_paths_are_named_after_the_first_domain() {
+        let certificate = get_certificate_paths(
+            Path::new("/var/lib/acme"),
+            &get_acme_certificate(&["Example.COM", "www.example.com"]),
+        )
+        .unwrap();
+
+        assert_eq!(
+            PathBuf::from("/var/lib/acme/example.com.pem"),
+            certificate.cert
+        );
+        assert_eq!(
+            PathBuf::from("/var/lib/acme/example.com.key"),
+            certificate.key
+        );
+    }
+
+    #[test]
+    fn certificate_paths_refuse_domains_that_leave_storage() {
+        for domains in [
+            &[][..],
+            &["*.example.com"],
+            &["../example.com"],
+            &[".example.com"],
+        ] {
+            let acme_certificate = get_acme_certificate(domains);
+            assert!(get_certificate_paths(Path::new("/var/lib/acme"), &acme_certificate).is_err());
+        }
+    }
+
+    // Orders a certificate from a local Pebble with http-01:
+    //   pebble -config test/config/pebble-config.json
+    //   ACME_CA=test/certs/pebble.minica.pem cargo test -- --ignored
+    // ACME_DIRECTORY_URL, ACME_HTTP_PORT and ACME_DOMAIN override the defaults.
+    #[tokio::test]
+    #[ignore]
+    async fn issues_a_certificate_with_http_01() {
+        let directory_url =
+            env::var("ACME_DIRECTORY_URL").unwrap_or("https://localhost:14000/dir".to_string());
+        let http_port = env::var("ACME_HTTP_PORT").unwrap_or("5002".to_string());
+        let domain = env::var("ACME_DOMAIN").unwrap_or("localhost".to_string());
+
+        let test_dir = env::temp_dir().join(format!("file_server_acme_{}", process::id()));
+        let _ = fs::remove_dir_all(&test_dir).await;
+        let challenge_dir = test_dir.join("www");
+        fs::create_dir_all(&challenge_dir).await.unwrap();
+
+        // the validation server fetches challenge files over plain http
+        let listener = TcpListener::bind(format!("0.0.0.0:{}", http_port))
+            .await
+            .unwrap();
+        let svc = Svc::new(get_challenge_requirements(challenge_dir.clone()));
+        let shutdown = CancellationToken::new();
+        let listener_shutdown = shutdown.clone();
+        tokio::spawn(async move {
+            let accept_fn = || listener.accept();
+            serve_listener(accept_fn, svc, None, listener_shutdown, TaskTracker::new()).await
+        });
+
+        let acme = AcmeConfig {
+            directory_url,
+            contact: None,
+            challenge: None,
+            storage: test_dir.join("acme"),
+            ca: env::var("ACME_CA").ok().map(PathBuf::from),
+            renew_before_days: None,
+            certificates: vec![get_acme_certificate(&[&domain])],
+        };
+        fs::create_dir_all(&acme.storage).await.unwrap();
+
+        let challenge_type = ChallengeType::Http01(challenge_dir);
+        let renewed = renew_certificates(&acme, &challenge_type, &Notify::new()).await;
+        shutdown.cancel();
+        assert!(renewed);
+
+        let certificate = get_certificate_paths(&acme.storage, &acme.certificates[0]).unwrap();
+        let remaining = get_time_to_expiration(&certificate.cert).await.unwrap();
+        assert!(Duration::from_secs(DAY) < remaining);
+
+        let _ = fs::remove_dir_all(&test_dir).await;
+    }
+}
@@ -0,0 +1,546 @@
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use bytes::Bytes;
+use http_body_util::{BodyExt, Full};
+use hyper::header::{HeaderMap, CONTENT_TYPE, LOCATION};
+use hyper::{Method, Request, StatusCode};
+use hyper_rustls::{HttpsConnector, HttpsConnectorBuilder};
+use hyper_util::client::legacy::connect::HttpConnector;
+use hyper_util::client::legacy::Client;
+use hyper_util::rt::TokioExecutor;
+use ring::digest::{digest, SHA256};
+use ring::rand::SystemRandom;
+use ring::signature::{EcdsaKeyPair, KeyPair, ECDSA_P256_SHA256_FIXED_SIGNING};
+use rustls::{ClientConfig, RootCertStore};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::path::PathBuf;
+
+use crate::tls::get_certs;
+
+const JOSE_JSON: &str = "application/jose+json";
+const REPLAY_NONCE: &str = "replay-nonce";
+const BAD_NONCE: &str = "urn:ietf:params:acme:error:badNonce";
+
+// https://www.rfc-editor.org/rfc/rfc8555#section-7.1.1
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Directory {
+    new_nonce: String,
+    new_account: String,
+    new_order: String,
+}
+
+// https://www.rfc-editor.org/rfc/rfc8555#section-7.1.3
+#[derive(Deserialize)]
+pub struct Order {
+    pub status: String,
+    pub authorizations: Vec<String>,
+    pub finalize: String,
+    pub certificate: Option<String>,
+}
+
+// https://www.rfc-editor.org/rfc/rfc8555#section-7.1.4
+#[derive(Deserialize)]
+pub struct Authorization {
+    pub status: String,
+    pub identifier: Identifier,
+    pub challenges: Vec<Challenge>,
+}
+
+#[derive(Deserialize)]
+pub struct Identifier {
+    pub value: String,
+}
+
+#[derive(Deserialize)]
+pub struct Challenge {
+    pub r#type: String,
+    pub url: String,
+    pub token: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Problem {
+    r#type: Option<String>,
+    detail: Option<String>,
+}
+
+// Requests are signed with the account key as flattened JWS objects.
+// https://www.rfc-editor.org/rfc/rfc8555#section-6.2
+pub struct AcmeClient {
+    client: Client<HttpsConnector<HttpConnector>, Full<Bytes>>,
+    directory: Directory,
+    key_pair: EcdsaKeyPair,
+    rng: SystemRandom,
+    account_url: Option<String>,
+    nonce: Option<String>,
+}
+
+impl AcmeClient {
+    // the account key is a PKCS#8 encoded P-256 key
+    pub async fn new(
+        directory_url: &str,
+        ca: &Option<PathBuf>,
+        account_key: &[u8],
+    ) -> Result<AcmeClient, String> {
+        let client = get_https_client(ca).await?;
+
+        let rng = SystemRandom::new();
+        let key_pair =
+            match EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, account_key, &rng) {
+                Ok(kp) => kp,
+                Err(e) => return Err(format!("acme account key: {}", e)),
+            };
+
+        let (_, body) = send(&client, Method::GET, directory_url).await?;
+        let directory = match serde_json::from_slice(&body) {
+            Ok(directory) => directory,
+            Err(e) => return Err(format!("{}: {}", directory_url, e)),
+        };
+
+        Ok(AcmeClient {
+            client,
+            directory,
+            key_pair,
+            rng,
+            account_url: None,
+            nonce: None,
+        })
+    }
+
+    // an existing account is returned for a known key
+    // https://www.rfc-editor.org/rfc/rfc8555#section-7.3
+    pub async fn register(&mut self, contact: &[String]) -> Result<(), String> {
+        let payload = json!({
+            "termsOfServiceAgreed": true,
+            "contact": contact,
+        });
+
+        let url = self.directory.new_account.clone();
+        let (headers, _) = self.post(&url, Some(payload)).await?;
+
+        self.account_url = match get_location(&headers) {
+            Some(location) => Some(location),
+            _ => return Err(format!("{}: no account location", url)),
+        };
+
+        Ok(())
+    }
+
+    pub async fn new_order(&mut self, domains: &[String]) -> Result<(String, Order), String> {
+        let identifiers: Vec<Value> = domains
+            .iter()
+            .map(|domain| json!({"type": "dns", "value": domain}))
+            .collect();
+
+        let url = self.directory.new_order.clone();
+        let (headers, body) = self
+            .post(&url, Some(json!({"identifiers": identifiers})))
+            .await?;
+
+        let order_url = match get_location(&headers) {
+            Some(location) => location,
+            _ => return Err(format!("{}: no order location", url)),
+        };
+
+        Ok((order_url, parse(&url, &body)?))
+    }
+
+    pub async fn get_order(&mut self, url: &str) -> Result<Order, String> {
+        let (_, body) = self.post(url, None).await?;
+        parse(url, &body)
+    }
+
+    pub async fn get_authorization(&mut self, url: &str) -> Result<Authorization, String> {
+        let (_, body) = self.post(url, None).await?;
+        parse(url, &body)
+    }
+
+    // tells the server the challenge is ready to be validated
+    pub async fn respond_to_challenge(&mut self, url: &str) -> Result<(), String> {
+        self.post(url, Some(json!({}))).await?;
+        Ok(())
+    }
+
+    pub async fn finalize(&mut self, url: &str, csr: &[u8]) -> Result<Order, String> {
+        let payload = json!({"csr": URL_SAFE_NO_PAD.encode(csr)});
+        let (_, body) = self.post(url, Some(payload)).await?;
+        parse(url, &body)
+    }
+
+    // PEM encoded certificate chain
+    pub async fn get_certificate(&mut self, url: &str) -> Result<String, String> {
+        let (_, body) = self.post(url, None).await?;
+        match String::from_utf8(body.to_vec()) {
+            Ok(pem) => Ok(pem),
+            Err(e) => Err(format!("{}: {}", url, e)),
+        }
+    }
+
+    // https://www.rfc-editor.org/rfc/rfc8555#section-8.1
+    pub fn get_key_authorization(&self, token: &str) -> String {
+        let (x, y) = self.get_public_coordinates();
+
+        // https://www.rfc-editor.org/rfc/rfc7638#section-3.2
+        let jwk = format!(
+            "{{\"crv\":\"P-256\",\"kty\":\"EC\",\"x\":\"{}\",\"y\":\"{}\"}}",
+            x, y
+        );
+
+        format!("{}.{}", token, get_thumbprint(&jwk))
+    }
+
+    // a stale nonce is retried once with a fresh one
+    async fn post(
+        &mut self,
+        url: &str,
+        payload: Option<Value>,
+    ) -> Result<(HeaderMap, Bytes), String> {
+        let (mut status, mut headers, mut body) = self.post_once(url, &payload).await?;
+        if is_bad_nonce(status, &body) {
+            (status, headers, body) = self.post_once(url, &payload).await?;
+        }
+
+        if !status.is_success() {
+            return Err(get_problem_detail(url, status, &body));
+        }
+
+        Ok((headers, body))
+    }
+
+    async fn post_once(
+        &mut self,
+        url: &str,
+        payload: &Option<Value>,
+    ) -> Result<(StatusCode, HeaderMap, Bytes), String> {
+        let nonce = match self.nonce.take() {
+            Some(nonce) => nonce,
+            _ => self.get_new_nonce().await?,
+        };
+
+        let jws = self.sign(url, &nonce, payload)?;
+
+        let req = match Request::builder()
+            .method(Method::POST)
+            .uri(url)
+            .header(CONTENT_TYPE, JOSE_JSON)
+            .body(Full::new(Bytes::from(jws)))
+        {
+            Ok(req) => req,
+            Err(e) => return Err(format!("{}: {}", url, e)),
+        };
+
+        let (status, headers, body) = request(&self.client, url, req).await?;
+        self.nonce = get_nonce(&headers);
+
+        Ok((status, headers, body))
+    }
+
+    async fn get_new_nonce(&mut self) -> Result<String, String> {
+        let url = self.directory.new_nonce.clone();
+        let (headers, _) = send(&self.client, Method::HEAD, &url).await?;
+
+        match get_nonce(&headers) {
+            Some(nonce) => Ok(nonce),
+            _ => Err(format!("{}: no nonce", url)),
+        }
+    }
+
+    // POST-as-GET requests have an empty payload
+    fn sign(&self, url: &str, nonce: &str, payload: &Option<Value>) -> Result<String, String> {
+        let mut protected = json!({
+            "alg": "ES256",
+            "nonce": nonce,
+            "url": url,
+        });
+
+        // only account creation identifies the account by its key
+        match &self.account_url {
+            Some(account_url) => protected["kid"] = json!(account_url),
+            _ => protected["jwk"] = self.get_jwk(),
+        }
+
+        let protected = URL_SAFE_NO_PAD.encode(protected.to_string());
+        let payload = match payload {
+            Some(payload) => URL_SAFE_NO_PAD.encode(payload.to_string()),
+            _ => String::new(),
+        };
+
+        let signing_input = format!("{}.{}", protected, payload);
+        let signature = match self.key_pair.sign(&self.rng, signing_input.as_bytes()) {
+            Ok(signature) => signature,
+            Err(e) => return Err(format!("{}: {}", url, e)),
+        };
+
+        let jws = json!({
+            "protected": protected,
+            "payload": payload,
+            "signature": URL_SAFE_NO_PAD.encode(signature.as_ref()),
+        });
+
+        Ok(jws.to_string())
+    }
+
+    fn get_jwk(&self) -> Value {
+        let (x, y) = self.get_public_coordinates();
+        json!({
+            "crv": "P-256",
+            "kty": "EC",
+            "x": x,
+            "y": y,
+        })
+    }
+
+    // uncompressed points are 0x04 followed by x and y
+    fn get_public_coordinates(&self) -> (String, String) {
+        let public_key = self.key_pair.public_key().as_ref();
+        (
+            URL_SAFE_NO_PAD.encode(&public_key[1..33]),
+            URL_SAFE_NO_PAD.encode(&public_key[33..65]),
+        )
+    }
+}
+
+// members of the jwk are ordered and free of whitespace
+// https://www.rfc-editor.org/rfc/rfc7638#section-3
+fn get_thumbprint(jwk: &str) -> String {
+    URL_SAFE_NO_PAD.encode(digest(&SHA256, jwk.as_bytes()))
+}
+
+// ACME servers are trusted through the web PKI and an optional extra CA
+// for test servers like Pebble
+async fn get_https_client(
+    ca: &Option<PathBuf>,
+) -> Result<Client<HttpsConnector<HttpConnector>, Full<Bytes>>, String> {
+    let mut roots = RootCertStore {
+        roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
+    };
+
+    if let Some(ca) = ca {
+        for cert in get_certs(ca).await? {
+            if let Err(e) = roots.add(cert) {
+                return Err(format!("{}: {}", ca.display(), e));
+            }
+        }
+    }
+
+    let tls_config = ClientConfig::builder()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+
+    let connector = HttpsConnectorBuilder::new()
+        .with_tls_config(tls_config)
+        .https_only()
+        .enable_http1()
+        .build();
+
+    Ok(Client::builder(TokioExecutor::new()).build(connector))
+}
+
+async fn send(
+    client: &Client<HttpsConnector<HttpConnector>, Full<Bytes>>,
+    method: Method,
+    url: &str,
+) -> Result<(HeaderMap, Bytes), String> {
+    let req = match Request::builder()
+        .method(method)
+        .uri(url)
+        .body(Full::new(Bytes::new()))
+    {
+        Ok(req) => req,
+        Err(e) => return Err(format!("{}: {}", url, e)),
+    };
+
+    let (status, headers, body) = request(client, url, req).await?;
+    if !status.is_success() {
+        return Err(get_problem_detail(url, status, &body));
+    }
+
+    Ok((headers, body))
+}
+
+async fn request(
+    client: &Client<HttpsConnector<HttpConnector>, Full<Bytes>>,
+    url: &str,
+    req: Request<Full<Bytes>>,
+) -> Result<(StatusCode, HeaderMap, Bytes), String> {
+    let res = match client.request(req).await {
+        Ok(res) => res,
+        Err(e) => return Err(format!("{}: {}", url, e)),
+    };
+
+    let (parts, body) = res.into_parts();
+    match body.collect().await {
+        Ok(collected) => Ok((parts.status, parts.headers, collected.to_bytes())),
+        Err(e) => Err(format!("{}: {}", url, e)),
+    }
+}
+
+fn parse<T: for<'de> Deserialize<'de>>(url: &str, body: &[u8]) -> Result<T, String> {
+    match serde_json::from_slice(body) {
+        Ok(parsed) => Ok(parsed),
+        Err(e) => Err(format!("{}: {}", url, e)),
+    }
+}
+
+// https://www.rfc-editor.org/rfc/rfc8555#section-6.7
+fn get_problem_detail(url: &str, status: StatusCode, body: &[u8]) -> String {
+    match serde_json::from_slice::<Problem>(body) {
+        Ok(Problem {
+            detail: Some(detail),
+            ..
+        }) => format!("{}: {} {}", url, status, detail),
+        _ => format!("{}: {}", url, status),
+    }
+}
+
+fn is_bad_nonce(status: StatusCode, body: &[u8]) -> bool {
+    if StatusCode::BAD_REQUEST != status {
+        return false;
+    }
+
+    match serde_json::from_slice::<Problem>(body) {
+        Ok(Problem {
+            r#type: Some(problem_type),
+            ..
+        }) => BAD_NONCE == problem_type,
+        _ => false,
+    }
+}
+
+fn get_nonce(headers: &HeaderMap) -> Option<String> {
+    let nonce = headers.get(REPLAY_NONCE)?.to_str().ok()?;
+    Some(nonce.to_string())
+}
+
+fn get_location(headers: &HeaderMap) -> Option<String> {
+    let location = headers.get(LOCATION)?.to_str().ok()?;
+    Some(location.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ring::signature::{UnparsedPublicKey, ECDSA_P256_SHA256_FIXED};
+
+    // https://www.rfc-editor.org/rfc/rfc7638#section-3.1
+    const RFC_7638_JWK: &str = concat!(
+        "{\"e\":\"AQAB\",\"kty\":\"RSA\",\"n\":\"0vx7agoebGcQSuuPiLJXZptN9nndrQmbXEps2aiAFbWhM78LhWx",
+        "4cbbfAAtVT86zwu1RK7aPFFxuhDR1L6tSoc_BJECPebWKRXjBZCiFV4n3oknjhMstn64tZ_2W-5JsGY4Hc5n9yBXArwl93lq",
+        "t7_RN5w6Cf0h4QyQ5v-65YGjQR0_FDW2QvzqY368QQMicAtaSqzs8KJZgnYb9c7d0zgdAZHzu6qMQvRL5hajrn1n91CbOpbI",
+        "SD08qNLyrdkt-bFTWhAI4vMQFh6WeZu0fM4lFd2NcRwr3XPksINHaQ-G_xBniIqbw0Ls1jF44-csFCur-kEgU8awapJzKnqD",
+        "Kgw\"}",
+    );
+    const RFC_7638_THUMBPRINT: &str = "NzbLsXh8uDCcd-6MNwXF4W_7noWXFZAfHkxZsRGC9Xs";
+
+    // a P-256 account key and its thumbprint
+    const ACCOUNT_KEY: &str = concat!(
+        "MIGHAgEAMBMGByqGSM49AgEGCCqGSM49AwEHBG0wawIBAQQg05sOYB1MSzE//UCYztAQnBGoG22FjFGpRU9tIyuxdlKhRANC",
+        "AAT/pZ+qPUK8ln5U7zydLmtBlVnsfQeuN6yNKYO4E+bJMRtxQr37vj7jKjar1E+MyGOcdEKIUfpLr9FQAlLsbVml",
+    );
+    const ACCOUNT_THUMBPRINT: &str = "zW8SsgUWIlXZRmjeaTq0FxuOOB3Wa_sV9bbgd0hdRg4";
+
+    async fn get_test_client() -> AcmeClient {
+        let account_key = base64::engine::general_purpose::STANDARD
+            .decode(ACCOUNT_KEY)
+            .unwrap();
+        let rng = SystemRandom::new();
+        let key_pair =
+            EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &account_key, &rng).unwrap();
+
+        AcmeClient {
+            client: get_https_client(&None).await.unwrap(),
+            directory: Directory {
+                new_nonce: "https://acme.test/new-nonce".to_string(),
+                new_account: "https://acme.test/new-account".to_string(),
+                new_order: "https://acme.test/new-order".to_string(),
+            },
+            key_pair,
+            rng,
+            account_url: None,
+            nonce: None,
+        }
+    }
+
+    fn decode_json(encoded: &Value) -> Value {
+        let decoded = URL_SAFE_NO_PAD.decode(encoded.as_str().unwrap()).unwrap();
+        serde_json::from_slice(&decoded).unwrap()
+    }
+
+    #[test]
+    fn thumbprint_matches_rfc_7638() {
+        assert_eq!(RFC_7638_THUMBPRINT, get_thumbprint(RFC_7638_JWK));
+    }
+
+    #[tokio::test]
+    async fn key_authorization_is_token_and_thumbprint() {
+        let acme_client = get_test_client().await;
+
+        assert_eq!(
+            format!("token-1.{}", ACCOUNT_THUMBPRINT),
+            acme_client.get_key_authorization("token-1")
+        );
+    }
+
+    #[tokio::test]
+    async fn sign_identifies_new_accounts_by_key() {
+        let acme_client = get_test_client().await;
+        let payload = json!({"termsOfServiceAgreed": true});
+
+        let jws: Value = serde_json::from_str(
+            &acme_client
+                .sign(
+                    "https://acme.test/new-account",
+                    "nonce-1",
+                    &Some(payload.clone()),
+                )
+                .unwrap(),
+        )
+        .unwrap();
+
+        let protected = decode_json(&jws["protected"]);
+        assert_eq!("ES256", protected["alg"]);
+        assert_eq!("nonce-1", protected["nonce"]);
+        assert_eq!("https://acme.test/new-account", protected["url"]);
+        assert_eq!(acme_client.get_jwk(), protected["jwk"]);
+        assert!(protected.get("kid").is_none());
+        assert_eq!(payload, decode_json(&jws["payload"]));
+
+        let signing_input = format!(
+            "{}.{}",
+            jws["protected"].as_str().unwrap(),
+            jws["payload"].as_str().unwrap()
+        );
+        let signature = URL_SAFE_NO_PAD
+            .decode(jws["signature"].as_str().unwrap())
+            .unwrap();
+        let public_key = UnparsedPublicKey::new(
+            &ECDSA_P256_SHA256_FIXED,
+            acme_client.key_pair.public_key().as_ref(),
+        );
+        assert!(public_key
+            .verify(signing_input.as_bytes(), &signature)
+            .is_ok());
+    }
+
+    #[tokio::test]
+    async fn sign_uses_the_account_url_once_registered() {
+        let mut acme_client = get_test_client().await;
+        acme_client.account_url = Some("https://acme.test/account/1".to_string());
+
+        let jws: Value = serde_json::from_str(
+            &acme_client
+                .sign("https://acme.test/order/1", "nonce-2", &None)
+                .unwrap(),
+        )
+        .unwrap();
+
+        let protected = decode_json(&jws["protected"]);
+        assert_eq!("https://acme.test/account/1", protected["kid"]);
+        assert!(protected.get("jwk").is_none());
+
+        // POST-as-GET
+        assert_eq!("", jws["payload"]);
+    }
+}
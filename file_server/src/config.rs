@@ -1,16 +1,106 @@
+use response::{ClientRule, CompressionCache, RangeLimits};
 use serde::{Deserialize, Serialize};
-use serde_json;
+use std::collections::HashMap;
 use std::env;
 use std::path;
-use std::path::{Path, PathBuf};
+use std::path::{Component, Path, PathBuf};
 use tokio::fs;
 
+// PEM encoded certificate chain and private key
 #[derive(Clone, Serialize, Deserialize, Debug)]
-pub struct Config {
+pub struct TlsCertificate {
+    pub cert: PathBuf,
+    pub key: PathBuf,
+}
+
+// client certificates are verified against a PEM encoded CA bundle
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct ClientAuth {
+    pub ca: PathBuf,
+    pub required: Option<bool>,
+    pub rules: Option<Vec<ClientRule>>,
+}
+
+// one certificate covers every domain of an entry
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct AcmeCertificate {
+    pub domains: Vec<String>,
+}
+
+// certificates issued by an ACME server like Let's Encrypt
+// https://www.rfc-editor.org/rfc/rfc8555
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct AcmeConfig {
+    pub directory_url: String,
+    pub contact: Option<Vec<String>>,
+    pub challenge: Option<String>,
+    pub storage: PathBuf,
+    pub ca: Option<PathBuf>,
+    pub renew_before_days: Option<u64>,
+    pub certificates: Vec<AcmeCertificate>,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct TlsConfig {
+    pub certificates: Vec<TlsCertificate>,
+    pub client_auth: Option<ClientAuth>,
+    pub acme: Option<AcmeConfig>,
+}
+
+// tcp listeners bind IPv4 or IPv6 addresses, unix listeners bind a socket file
+// listeners speak tls unless tls is false
+#[derive(Clone, Serialize, Deserialize, Debug)]
+#[serde(untagged)]
+pub enum ListenerConfig {
+    Tcp {
+        host_and_port: String,
+        tls: Option<bool>,
+    },
+    Unix {
+        path: PathBuf,
+        mode: Option<String>,
+        owner: Option<String>,
+        group: Option<String>,
+        tls: Option<bool>,
+    },
+}
+
+// a plain HTTP listener that redirects to HTTPS
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct HttpRedirect {
     pub host_and_port: String,
+    pub acme_challenge_directory: Option<PathBuf>,
+}
+
+// https://www.rfc-editor.org/rfc/rfc6797#section-6.1
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct Hsts {
+    pub max_age: u64,
+    pub include_subdomains: Option<bool>,
+    pub preload: Option<bool>,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct Config {
+    // empty when systemd passes the sockets
+    #[serde(default)]
+    pub listeners: Vec<ListenerConfig>,
+    pub http3_host_and_port: Option<String>,
     pub directory: PathBuf,
     pub content_encodings: Option<Vec<String>>,
+    pub encoding_extensions: Option<HashMap<String, Vec<String>>>,
     pub filepath_404: Option<PathBuf>,
+    pub range_limits: Option<RangeLimits>,
+    pub content_location: Option<bool>,
+    pub decompress_fallback: Option<bool>,
+    pub skip_stale_encodings: Option<bool>,
+    pub use_as_dictionary: Option<Vec<String>>,
+    pub compression_cache: Option<CompressionCache>,
+    pub tls: Option<TlsConfig>,
+    pub http_redirect: Option<HttpRedirect>,
+    pub hsts: Option<Hsts>,
+    pub drain_timeout: Option<u64>,
+    pub max_open_files: Option<u64>,
 }
 
 impl Config {
@@ -21,10 +111,26 @@ impl Config {
         };
 
         Ok(Config {
-            host_and_port: "0.0.0.0:3000".to_string(),
+            listeners: vec![ListenerConfig::Tcp {
+                host_and_port: "0.0.0.0:3000".to_string(),
+                tls: None,
+            }],
+            http3_host_and_port: None,
             directory: curr_dir,
             content_encodings: None,
+            encoding_extensions: None,
             filepath_404: None,
+            range_limits: None,
+            content_location: None,
+            decompress_fallback: None,
+            skip_stale_encodings: None,
+            use_as_dictionary: None,
+            compression_cache: None,
+            tls: None,
+            http_redirect: None,
+            hsts: None,
+            drain_timeout: None,
+            max_open_files: None,
         })
     }
 
@@ -35,13 +141,25 @@ impl Config {
             Err(e) => return Err(e.to_string()),
         };
 
-        let mut config: Config = match serde_json::from_str(&config_json) {
+        let config_value: serde_json::Value = match serde_json::from_str(&config_json) {
+            Ok(j) => j,
+            Err(e) => return Err(e.to_string()),
+        };
+
+        // configs written before listeners would otherwise have none
+        if config_value.get("host_and_port").is_some() {
+            return Err(
+                "host_and_port was replaced by listeners: [{\"host_and_port\": ...}]".to_string(),
+            );
+        }
+
+        let mut config: Config = match serde_json::from_value(config_value) {
             Ok(j) => j,
             Err(e) => return Err(e.to_string()),
         };
 
         // get target directory
-        let config_path = match path::absolute(&source_path) {
+        let config_path = match path::absolute(source_path) {
             Ok(pb) => pb,
             Err(e) => return Err(e.to_string()),
         };
@@ -69,10 +187,122 @@ impl Config {
             };
         }
 
+        // unix sockets relative to config path
+        for listener in &mut config.listeners {
+            if let ListenerConfig::Unix { path, .. } = listener {
+                *path = match path::absolute(parent_dir.join(&path)) {
+                    Ok(pb) => pb,
+                    Err(e) => return Err(e.to_string()),
+                };
+            }
+        }
+
+        // cache directory relative to config path
+        if let Some(cache) = &mut config.compression_cache {
+            cache.directory = match path::absolute(parent_dir.join(&cache.directory)) {
+                Ok(pb) => pb,
+                Err(e) => return Err(e.to_string()),
+            };
+
+            // cached files must never be served as regular files
+            // and eviction must never reach served files
+            let cache_dir = get_canonical_path(&cache.directory).await;
+            let served_dir = get_canonical_path(&config.directory).await;
+            if cache_dir.starts_with(&served_dir) {
+                return Err("compression_cache directory resides in directory".to_string());
+            }
+
+            if served_dir.starts_with(&cache_dir) {
+                return Err("directory resides in compression_cache directory".to_string());
+            }
+        }
+
+        // certificates relative to config path
+        if let Some(tls) = &mut config.tls {
+            for certificate in &mut tls.certificates {
+                certificate.cert = match path::absolute(parent_dir.join(&certificate.cert)) {
+                    Ok(pb) => pb,
+                    Err(e) => return Err(e.to_string()),
+                };
+
+                certificate.key = match path::absolute(parent_dir.join(&certificate.key)) {
+                    Ok(pb) => pb,
+                    Err(e) => return Err(e.to_string()),
+                };
+            }
+
+            if let Some(client_auth) = &mut tls.client_auth {
+                client_auth.ca = match path::absolute(parent_dir.join(&client_auth.ca)) {
+                    Ok(pb) => pb,
+                    Err(e) => return Err(e.to_string()),
+                };
+            }
+
+            if let Some(acme) = &mut tls.acme {
+                acme.storage = match path::absolute(parent_dir.join(&acme.storage)) {
+                    Ok(pb) => pb,
+                    Err(e) => return Err(e.to_string()),
+                };
+
+                if let Some(ca) = &mut acme.ca {
+                    *ca = match path::absolute(parent_dir.join(&ca)) {
+                        Ok(pb) => pb,
+                        Err(e) => return Err(e.to_string()),
+                    };
+                }
+            }
+        }
+
+        // challenge files relative to config path
+        if let Some(HttpRedirect {
+            acme_challenge_directory: Some(challenge_dir),
+            ..
+        }) = &mut config.http_redirect
+        {
+            *challenge_dir = match path::absolute(parent_dir.join(&challenge_dir)) {
+                Ok(pb) => pb,
+                Err(e) => return Err(e.to_string()),
+            };
+        }
+
         Ok(config)
     }
 }
 
+// Resolves "..", "." and symlinks so directories can be compared.
+// Components that don't exist yet are resolved lexically.
+async fn get_canonical_path(target_path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in target_path.components() {
+        match component {
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            Component::CurDir => {}
+            _ => normalized.push(component),
+        }
+    }
+
+    let mut missing = Vec::new();
+    let mut existing = normalized.as_path();
+    loop {
+        if let Ok(canonical) = fs::canonicalize(existing).await {
+            return missing
+                .iter()
+                .rev()
+                .fold(canonical, |path, name| path.join(name));
+        }
+
+        match (existing.parent(), existing.file_name()) {
+            (Some(parent), Some(name)) => {
+                missing.push(name);
+                existing = parent;
+            }
+            _ => return normalized,
+        }
+    }
+}
+
 fn get_path_relative_to_origin(source_dir: &Path, filepath: &PathBuf) -> Result<PathBuf, String> {
     let target_path = source_dir.join(filepath);
     let target_path_abs = match path::absolute(target_path) {
@@ -0,0 +1,52 @@
+use std::time::Duration;
+use tokio::time;
+
+use crate::accept;
+
+const REPORT_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Clone, Copy, Default, PartialEq)]
+struct Counters {
+    accept_failures: u64,
+    stale_encodings_skipped: u64,
+}
+
+// counters are logged once a minute while they change
+pub fn spawn_reporter() {
+    tokio::spawn(async move {
+        let mut reported = Counters::default();
+        loop {
+            time::sleep(REPORT_INTERVAL).await;
+
+            let counters = get_counters();
+            if reported != counters {
+                log_counters(&counters);
+                reported = counters;
+            }
+        }
+    });
+}
+
+pub fn report() {
+    log_counters(&get_counters());
+}
+
+fn get_counters() -> Counters {
+    Counters {
+        accept_failures: accept::accept_failures(),
+        stale_encodings_skipped: response::stale_encodings_skipped(),
+    }
+}
+
+fn log_counters(counters: &Counters) {
+    if 0 < counters.accept_failures {
+        println!("file_server: {} failed accepts", counters.accept_failures);
+    }
+
+    if 0 < counters.stale_encodings_skipped {
+        println!(
+            "file_server: {} stale encoded files skipped",
+            counters.stale_encodings_skipped
+        );
+    }
+}
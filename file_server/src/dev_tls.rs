@@ -0,0 +1,189 @@
+use rcgen::{
+    BasicConstraints, CertificateParams, CidrSubnet, DnType, ExtendedKeyUsagePurpose,
+    GeneralSubtree, IsCa, Issuer, KeyPair, KeyUsagePurpose, NameConstraints,
+};
+use std::env;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::fs;
+use x509_parser::time::ASN1Time;
+
+use crate::config::{TlsCertificate, TlsConfig};
+use crate::tls::{get_time_to_expiration, write_key};
+
+const DEV_TLS_NAMES: [&str; 3] = ["localhost", "127.0.0.1", "::1"];
+
+const CA_VALIDITY: Duration = Duration::from_secs(10 * 365 * 24 * 60 * 60);
+// some clients refuse leaf certificates valid for more than 398 days
+const LEAF_VALIDITY: Duration = Duration::from_secs(397 * 24 * 60 * 60);
+const RENEW_BEFORE: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+
+// Serves a localhost certificate signed by a local development CA. Client
+// certificate settings are kept, ACME is not used.
+pub async fn get_dev_tls_config(tls: Option<TlsConfig>) -> Result<TlsConfig, String> {
+    let certificate = get_dev_tls_certificate().await?;
+
+    Ok(TlsConfig {
+        certificates: vec![certificate],
+        client_auth: tls.and_then(|tls| tls.client_auth),
+        acme: None,
+    })
+}
+
+// The CA and leaf certificate are created once and reused across runs so
+// the CA only needs to be trusted once.
+async fn get_dev_tls_certificate() -> Result<TlsCertificate, String> {
+    let dev_tls_dir = get_dev_tls_dir()?;
+    if let Err(e) = fs::create_dir_all(&dev_tls_dir).await {
+        return Err(e.to_string());
+    }
+
+    let ca_cert = dev_tls_dir.join("ca.pem");
+    let ca_key = dev_tls_dir.join("ca.key");
+    let certificate = TlsCertificate {
+        cert: dev_tls_dir.join("localhost.pem"),
+        key: dev_tls_dir.join("localhost.key"),
+    };
+
+    let (issuer, ca_created) = match get_issuer(&ca_cert, &ca_key).await {
+        Some(issuer) => (issuer, false),
+        _ => (create_ca(&ca_cert, &ca_key).await?, true),
+    };
+
+    // a new CA can't verify the previous leaf
+    let leaf_is_current = certificate_is_current(&certificate.cert).await
+        && fs::try_exists(&certificate.key).await.unwrap_or(false);
+    if ca_created || !leaf_is_current {
+        create_leaf(&certificate, &issuer).await?;
+    }
+
+    println!(
+        "file_server: trust the development CA at {}",
+        ca_cert.display()
+    );
+
+    Ok(certificate)
+}
+
+fn get_dev_tls_dir() -> Result<PathBuf, String> {
+    if let Some(config_home) = env::var_os("XDG_CONFIG_HOME") {
+        return Ok(PathBuf::from(config_home).join("file_server/dev_tls"));
+    }
+
+    match env::var_os("HOME") {
+        Some(home) => Ok(PathBuf::from(home).join(".config/file_server/dev_tls")),
+        _ => Err("--dev-tls requires HOME or XDG_CONFIG_HOME".to_string()),
+    }
+}
+
+async fn get_issuer(ca_cert: &Path, ca_key: &Path) -> Option<Issuer<'static, KeyPair>> {
+    if !certificate_is_current(ca_cert).await {
+        return None;
+    }
+
+    let cert_pem = fs::read_to_string(ca_cert).await.ok()?;
+    let key_pem = fs::read_to_string(ca_key).await.ok()?;
+    let key_pair = KeyPair::from_pem(&key_pem).ok()?;
+
+    Issuer::from_ca_cert_pem(&cert_pem, key_pair).ok()
+}
+
+async fn create_ca(ca_cert: &Path, ca_key: &Path) -> Result<Issuer<'static, KeyPair>, String> {
+    let mut params = CertificateParams::default();
+    params
+        .distinguished_name
+        .push(DnType::CommonName, "file_server development CA");
+    params.is_ca = IsCa::Ca(BasicConstraints::Constrained(0));
+    params.key_usages = vec![KeyUsagePurpose::KeyCertSign, KeyUsagePurpose::CrlSign];
+    params.not_before = get_asn1_time(Duration::ZERO)?.to_datetime();
+    params.not_after = get_asn1_time(CA_VALIDITY)?.to_datetime();
+
+    // a trusted development CA can't vouch for public hosts
+    params.name_constraints = Some(NameConstraints {
+        permitted_subtrees: vec![
+            GeneralSubtree::DnsName("localhost".to_string()),
+            GeneralSubtree::IpAddress(CidrSubnet::from_v4_prefix([127, 0, 0, 0], 8)),
+            GeneralSubtree::IpAddress(CidrSubnet::from_v6_prefix(1u128.to_be_bytes(), 128)),
+        ],
+        excluded_subtrees: Vec::new(),
+    });
+
+    let key_pair = match KeyPair::generate() {
+        Ok(kp) => kp,
+        Err(e) => return Err(e.to_string()),
+    };
+
+    let cert = match params.self_signed(&key_pair) {
+        Ok(cert) => cert,
+        Err(e) => return Err(e.to_string()),
+    };
+
+    write_key(ca_key, &key_pair.serialize_pem()).await?;
+    if let Err(e) = fs::write(ca_cert, cert.pem()).await {
+        return Err(e.to_string());
+    }
+
+    println!("file_server: created development CA {}", ca_cert.display());
+
+    Ok(Issuer::new(params, key_pair))
+}
+
+async fn create_leaf(
+    certificate: &TlsCertificate,
+    issuer: &Issuer<'static, KeyPair>,
+) -> Result<(), String> {
+    let names: Vec<String> = DEV_TLS_NAMES.iter().map(|name| name.to_string()).collect();
+    let mut params = match CertificateParams::new(names) {
+        Ok(params) => params,
+        Err(e) => return Err(e.to_string()),
+    };
+    params
+        .distinguished_name
+        .push(DnType::CommonName, "localhost");
+    params.key_usages = vec![KeyUsagePurpose::DigitalSignature];
+    params.extended_key_usages = vec![ExtendedKeyUsagePurpose::ServerAuth];
+    params.use_authority_key_identifier_extension = true;
+    params.not_before = get_asn1_time(Duration::ZERO)?.to_datetime();
+    params.not_after = get_asn1_time(LEAF_VALIDITY)?.to_datetime();
+
+    let key_pair = match KeyPair::generate() {
+        Ok(kp) => kp,
+        Err(e) => return Err(e.to_string()),
+    };
+
+    let cert = match params.signed_by(&key_pair, issuer) {
+        Ok(cert) => cert,
+        Err(e) => return Err(e.to_string()),
+    };
+
+    write_key(&certificate.key, &key_pair.serialize_pem()).await?;
+    match fs::write(&certificate.cert, cert.pem()).await {
+        Ok(_) => Ok(()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+// certificates are replaced well before they expire
+async fn certificate_is_current(filepath: &Path) -> bool {
+    match get_time_to_expiration(filepath).await {
+        Some(remaining) => remaining > RENEW_BEFORE,
+        _ => false,
+    }
+}
+
+fn get_asn1_time(from_now: Duration) -> Result<ASN1Time, String> {
+    let timestamp = match SystemTime::now().checked_add(from_now) {
+        Some(time) => time.duration_since(UNIX_EPOCH),
+        _ => return Err("certificate validity out of range".to_string()),
+    };
+
+    let secs = match timestamp {
+        Ok(duration) => duration.as_secs() as i64,
+        Err(e) => return Err(e.to_string()),
+    };
+
+    match ASN1Time::from_timestamp(secs) {
+        Ok(time) => Ok(time),
+        Err(e) => Err(e.to_string()),
+    }
+}
@@ -0,0 +1,474 @@
+use bytes::Bytes;
+use http_body_util::BodyExt;
+use hyper::http::Response;
+use hyper::service::Service;
+use quinn::{Endpoint, EndpointConfig, TokioRuntime, VarInt};
+use rustls::pki_types::CertificateDer;
+use std::mem;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::time;
+use tokio_util::sync::CancellationToken;
+use tokio_util::task::TaskTracker;
+
+use crate::listener::get_socket_address;
+use crate::service::Svc;
+use crate::tls::get_client_names;
+
+type RequestResolver = h3::server::RequestResolver<h3_quinn::Connection, Bytes>;
+
+// Connections accepted since the last suspend share a token. Cancelling it
+// sends them a GOAWAY, a child of shutdown replaces it for later connections.
+// While suspended, reloads leave the endpoint refusing connections.
+pub struct Goaway {
+    token: CancellationToken,
+    suspended: bool,
+}
+
+pub type SharedGoaway = Arc<Mutex<Goaway>>;
+
+// https://www.rfc-editor.org/rfc/rfc9114#section-8.1
+const H3_NO_ERROR: u32 = 0x100;
+const SUSPEND_TIMEOUT: Duration = Duration::from_secs(10);
+
+pub fn bind(host_and_port: &str) -> Result<UdpSocket, String> {
+    let address = match get_socket_address(host_and_port) {
+        Some(addr) => addr,
+        _ => return Err(format!("{}: no socket address", host_and_port)),
+    };
+
+    match UdpSocket::bind(address) {
+        Ok(socket) => Ok(socket),
+        Err(e) => Err(format!("{}: {}", host_and_port, e)),
+    }
+}
+
+pub fn get_endpoint(
+    socket: UdpSocket,
+    server_config: quinn::ServerConfig,
+) -> Result<Endpoint, String> {
+    let runtime = Arc::new(TokioRuntime);
+    match Endpoint::new(
+        EndpointConfig::default(),
+        Some(server_config),
+        socket,
+        runtime,
+    ) {
+        Ok(endpoint) => Ok(endpoint),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+pub fn get_shared_goaway(shutdown: &CancellationToken) -> SharedGoaway {
+    Arc::new(Mutex::new(Goaway {
+        token: shutdown.child_token(),
+        suspended: false,
+    }))
+}
+
+// reloaded certificates are applied on resume instead
+pub fn set_server_config(
+    endpoint: &Endpoint,
+    goaway: &SharedGoaway,
+    server_config: quinn::ServerConfig,
+) -> Result<(), String> {
+    match goaway.lock() {
+        Ok(current) if current.suspended => Ok(()),
+        Ok(_) => {
+            endpoint.set_server_config(Some(server_config));
+            Ok(())
+        }
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+// QUIC connections can't move to another process. New connections are
+// refused, open ones get a GOAWAY and are closed once their requests finish
+// or after SUSPEND_TIMEOUT. The endpoint then moves to an unused socket so
+// two processes never read from one. It stays open so it can resume.
+pub async fn suspend(
+    endpoint: &Endpoint,
+    goaway: &SharedGoaway,
+    shutdown: &CancellationToken,
+) -> Result<(), String> {
+    let suspended = match goaway.lock() {
+        Ok(mut current) => {
+            current.suspended = true;
+            endpoint.set_server_config(None);
+            mem::replace(&mut current.token, shutdown.child_token())
+        }
+        Err(e) => return Err(e.to_string()),
+    };
+    suspended.cancel();
+    endpoint.wait_idle().await;
+
+    let address = match endpoint.local_addr() {
+        Ok(SocketAddr::V4(_)) => SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0)),
+        Ok(SocketAddr::V6(_)) => SocketAddr::from((Ipv6Addr::UNSPECIFIED, 0)),
+        Err(e) => return Err(e.to_string()),
+    };
+
+    let socket = match UdpSocket::bind(address) {
+        Ok(socket) => socket,
+        Err(e) => return Err(e.to_string()),
+    };
+
+    match endpoint.rebind(socket) {
+        Ok(_) => Ok(()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+// serves http/3 again after an upgrade failed
+pub fn resume(
+    endpoint: &Endpoint,
+    socket: &UdpSocket,
+    goaway: &SharedGoaway,
+    server_config: quinn::ServerConfig,
+) -> Result<(), String> {
+    let socket = match socket.try_clone() {
+        Ok(socket) => socket,
+        Err(e) => return Err(e.to_string()),
+    };
+
+    if let Err(e) = endpoint.rebind(socket) {
+        return Err(e.to_string());
+    }
+
+    match goaway.lock() {
+        Ok(mut current) => {
+            current.suspended = false;
+            endpoint.set_server_config(Some(server_config));
+            Ok(())
+        }
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+pub async fn serve(
+    endpoint: Endpoint,
+    svc: Svc,
+    goaway: SharedGoaway,
+    shutdown: CancellationToken,
+    connections: TaskTracker,
+) {
+    loop {
+        let incoming = tokio::select! {
+            incoming = endpoint.accept() => incoming,
+            _ = shutdown.cancelled() => break,
+        };
+
+        let incoming = match incoming {
+            Some(incoming) => incoming,
+            _ => break,
+        };
+
+        let goaway = match goaway.lock() {
+            Ok(current) => current.token.clone(),
+            _ => shutdown.clone(),
+        };
+        let svc = svc.clone();
+        let shutdown = shutdown.clone();
+        let requests = connections.clone();
+
+        connections.spawn(async move {
+            // failed handshakes are the client's problem
+            let connection = match incoming.await {
+                Ok(conn) => conn,
+                _ => return,
+            };
+
+            let peer_certificates = connection
+                .peer_identity()
+                .and_then(|identity| identity.downcast::<Vec<CertificateDer>>().ok());
+            let client_names = get_client_names(peer_certificates.as_deref().map(|pc| &pc[..]));
+
+            serve_connection(
+                connection,
+                svc.with_client_names(client_names),
+                goaway,
+                shutdown,
+                requests,
+            )
+            .await;
+        });
+    }
+
+    // new connections are refused while draining
+    endpoint.set_server_config(None);
+}
+
+// connections still open after draining are closed
+pub fn close(endpoint: &Endpoint) {
+    endpoint.close(VarInt::from_u32(H3_NO_ERROR), b"shutdown");
+}
+
+async fn serve_connection(
+    connection: quinn::Connection,
+    svc: Svc,
+    goaway: CancellationToken,
+    shutdown: CancellationToken,
+    requests: TaskTracker,
+) {
+    let mut h3_connection =
+        match h3::server::Connection::new(h3_quinn::Connection::new(connection)).await {
+            Ok(conn) => conn,
+            _ => return,
+        };
+
+    // requests are served until the client closes the connection
+    loop {
+        let accepted = tokio::select! {
+            accepted = h3_connection.accept() => accepted,
+            _ = goaway.cancelled() => break,
+        };
+
+        let resolver = match accepted {
+            Ok(Some(resolver)) => resolver,
+            _ => return,
+        };
+
+        requests.spawn(serve_request(resolver, svc.clone()));
+    }
+
+    // a GOAWAY lets in-flight requests finish and refuses new ones
+    if h3_connection.shutdown(0).await.is_err() {
+        return;
+    }
+
+    let drained = async {
+        while let Ok(Some(resolver)) = h3_connection.accept().await {
+            requests.spawn(serve_request(resolver, svc.clone()));
+        }
+    };
+
+    // Shutdown leaves in-flight requests to the drain timeout. An upgrade
+    // closes the connection after SUSPEND_TIMEOUT when the client keeps it.
+    if shutdown.is_cancelled() {
+        drained.await;
+    } else {
+        let _ = time::timeout(SUSPEND_TIMEOUT, drained).await;
+    }
+}
+
+async fn serve_request(resolver: RequestResolver, svc: Svc) {
+    let (req, mut stream) = match resolver.resolve_request().await {
+        Ok(rs) => rs,
+        _ => return,
+    };
+
+    let res = match svc.call(req).await {
+        Ok(res) => res,
+        _ => return,
+    };
+
+    let (parts, mut body) = res.into_parts();
+    if stream
+        .send_response(Response::from_parts(parts, ()))
+        .await
+        .is_err()
+    {
+        return;
+    }
+
+    while let Some(frame) = body.frame().await {
+        let data = match frame.map(|f| f.into_data()) {
+            Ok(Ok(data)) => data,
+            Ok(_) => continue,
+            _ => return,
+        };
+
+        if stream.send_data(data).await.is_err() {
+            return;
+        }
+    }
+
+    let _ = stream.finish().await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::Buf;
+    use hyper::header::ALT_SVC;
+    use hyper::Request;
+    use hyper_util::rt::TokioIo;
+    use quinn::crypto::rustls::QuicClientConfig;
+    use rcgen::{generate_simple_self_signed, CertifiedKey};
+    use rustls::pki_types::ServerName;
+    use rustls::version::TLS13;
+    use rustls::{ClientConfig, RootCertStore};
+    use std::env;
+    use std::future::poll_fn;
+    use std::path::PathBuf;
+    use std::process;
+    use std::sync::RwLock;
+    use tokio::fs;
+    use tokio::net::{TcpListener, TcpStream};
+    use tokio_rustls::TlsConnector;
+
+    use crate::config::{TlsCertificate, TlsConfig};
+    use crate::tls::{get_quic_server_config, get_server_config};
+    use crate::{get_alt_svc, serve_listener};
+    use response::{AvailableEncodings, RangeLimits, ServiceRequirements};
+
+    const BODY: &str = "served over http/3";
+
+    async fn get_test_dir(name: &str) -> PathBuf {
+        let test_dir = env::temp_dir().join(format!("file_server_{}_{}", name, process::id()));
+        let _ = fs::remove_dir_all(&test_dir).await;
+        fs::create_dir_all(&test_dir).await.unwrap();
+        test_dir
+    }
+
+    fn get_client_config(cert: &CertifiedKey<rcgen::KeyPair>, alpn: &[u8]) -> ClientConfig {
+        let mut roots = RootCertStore::empty();
+        roots.add(cert.cert.der().clone()).unwrap();
+
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let mut client_config = ClientConfig::builder_with_provider(provider)
+            .with_protocol_versions(&[&TLS13])
+            .unwrap()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        client_config.alpn_protocols = vec![alpn.to_vec()];
+
+        client_config
+    }
+
+    #[tokio::test]
+    async fn serves_files_over_http3_and_advertises_it_over_tcp() {
+        let test_dir = get_test_dir("http3").await;
+        fs::write(test_dir.join("index.txt"), BODY).await.unwrap();
+
+        let cert = generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        fs::write(test_dir.join("cert.pem"), cert.cert.pem())
+            .await
+            .unwrap();
+        fs::write(test_dir.join("key.pem"), cert.signing_key.serialize_pem())
+            .await
+            .unwrap();
+
+        let tls = TlsConfig {
+            certificates: vec![TlsCertificate {
+                cert: test_dir.join("cert.pem"),
+                key: test_dir.join("key.pem"),
+            }],
+            client_auth: None,
+            acme: None,
+        };
+
+        let shutdown = CancellationToken::new();
+        let connections = TaskTracker::new();
+        let svc = Svc::new(ServiceRequirements {
+            directory: test_dir.clone(),
+            available_encodings: AvailableEncodings::default(),
+            fallback_404: None,
+            range_limits: RangeLimits::default(),
+            content_location: false,
+            decompress_fallback: false,
+            skip_stale_encodings: false,
+            use_as_dictionary: None,
+            compression_cache: None,
+            client_rules: Vec::new(),
+        });
+
+        // http/3
+        let quic_server_config = get_quic_server_config(&tls).await.unwrap();
+        let endpoint = get_endpoint(bind("127.0.0.1:0").unwrap(), quic_server_config).unwrap();
+        let quic_address = endpoint.local_addr().unwrap();
+        tokio::spawn(serve(
+            endpoint.clone(),
+            svc.clone(),
+            get_shared_goaway(&shutdown),
+            shutdown.clone(),
+            connections.clone(),
+        ));
+
+        // tcp
+        let server_config = get_server_config(&tls).await.unwrap();
+        let tcp_listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let tcp_address = tcp_listener.local_addr().unwrap();
+        let tcp_svc = svc.with_alt_svc(get_alt_svc(&Some(endpoint.clone())));
+        let tls_config = Some(Arc::new(RwLock::new(server_config)));
+        let tcp_shutdown = shutdown.clone();
+        let tcp_connections = connections.clone();
+        tokio::spawn(async move {
+            let accept_fn = || tcp_listener.accept();
+            serve_listener(
+                accept_fn,
+                tcp_svc,
+                tls_config,
+                tcp_shutdown,
+                tcp_connections,
+            )
+            .await
+        });
+
+        // fetch a file over http/3
+        let quic_client_config =
+            QuicClientConfig::try_from(get_client_config(&cert, b"h3")).unwrap();
+        let mut client_endpoint = Endpoint::client("127.0.0.1:0".parse().unwrap()).unwrap();
+        client_endpoint
+            .set_default_client_config(quinn::ClientConfig::new(Arc::new(quic_client_config)));
+
+        let connection = client_endpoint
+            .connect(quic_address, "localhost")
+            .unwrap()
+            .await
+            .unwrap();
+        let (mut driver, mut send_request) = h3::client::new(h3_quinn::Connection::new(connection))
+            .await
+            .unwrap();
+        tokio::spawn(async move { poll_fn(|cx| driver.poll_close(cx)).await });
+
+        let req = Request::get("https://localhost/index.txt")
+            .body(())
+            .unwrap();
+        let mut stream = send_request.send_request(req).await.unwrap();
+        stream.finish().await.unwrap();
+
+        let res = stream.recv_response().await.unwrap();
+        let mut body = Vec::new();
+        while let Some(mut chunk) = stream.recv_data().await.unwrap() {
+            while chunk.has_remaining() {
+                let bytes = chunk.chunk();
+                body.extend_from_slice(bytes);
+                chunk.advance(bytes.len());
+            }
+        }
+
+        assert_eq!(200, res.status().as_u16());
+        assert_eq!(BODY.as_bytes(), &body[..]);
+
+        // the same file over tcp advertises the http/3 port
+        let tls_connector = TlsConnector::from(Arc::new(get_client_config(&cert, b"http/1.1")));
+        let tcp_stream = TcpStream::connect(tcp_address).await.unwrap();
+        let tls_stream = tls_connector
+            .connect(ServerName::try_from("localhost").unwrap(), tcp_stream)
+            .await
+            .unwrap();
+        let (mut sender, tcp_connection) =
+            hyper::client::conn::http1::handshake(TokioIo::new(tls_stream))
+                .await
+                .unwrap();
+        tokio::spawn(tcp_connection);
+
+        let req = Request::get("/index.txt")
+            .header("host", "localhost")
+            .body(http_body_util::Empty::<Bytes>::new())
+            .unwrap();
+        let res = sender.send_request(req).await.unwrap();
+
+        assert_eq!(200, res.status().as_u16());
+        assert_eq!(
+            format!("h3=\":{}\"; ma=86400", quic_address.port()),
+            res.headers()[ALT_SVC].to_str().unwrap()
+        );
+
+        shutdown.cancel();
+        close(&endpoint);
+        let _ = fs::remove_dir_all(&test_dir).await;
+    }
+}
@@ -0,0 +1,278 @@
+use socket2::{Domain, Protocol, Socket, Type};
+use std::ffi::CString;
+use std::fs;
+use std::io;
+use std::mem;
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::os::fd::{AsRawFd, RawFd};
+use std::os::unix::fs::{chown, FileTypeExt, PermissionsExt};
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+use std::ptr;
+use tokio::net::{TcpListener, UnixListener};
+
+use crate::config::ListenerConfig;
+
+const BACKLOG: i32 = 1024;
+const PASSWD_BUFFER_SIZE: usize = 16384;
+const SOCKET_UMASK: libc::mode_t = 0o177;
+
+pub enum Listener {
+    Tcp(TcpListener),
+    Unix(UnixListener),
+}
+
+pub fn bind(listener_config: &ListenerConfig) -> Result<Listener, String> {
+    match listener_config {
+        ListenerConfig::Tcp { host_and_port, .. } => bind_tcp(host_and_port),
+        ListenerConfig::Unix {
+            path,
+            mode,
+            owner,
+            group,
+            ..
+        } => bind_unix(path, mode, owner, group),
+    }
+}
+
+pub fn get_name(listener_config: &ListenerConfig) -> String {
+    match listener_config {
+        ListenerConfig::Tcp { host_and_port, .. } => host_and_port.clone(),
+        ListenerConfig::Unix { path, .. } => format!("unix:{}", path.display()),
+    }
+}
+
+pub fn is_tls(listener_config: &ListenerConfig) -> bool {
+    match listener_config {
+        ListenerConfig::Tcp { tls, .. } => tls.unwrap_or(true),
+        ListenerConfig::Unix { tls, .. } => tls.unwrap_or(true),
+    }
+}
+
+pub fn get_local_name(listener: &Listener) -> String {
+    let local_name = match listener {
+        Listener::Tcp(tcp_listener) => tcp_listener.local_addr().map(|addr| addr.to_string()),
+        Listener::Unix(unix_listener) => {
+            unix_listener
+                .local_addr()
+                .map(|addr| match addr.as_pathname() {
+                    Some(path) => format!("unix:{}", path.display()),
+                    _ => "unix".to_string(),
+                })
+        }
+    };
+
+    local_name.unwrap_or_else(|e| e.to_string())
+}
+
+pub fn get_raw_fd(listener: &Listener) -> RawFd {
+    match listener {
+        Listener::Tcp(tcp_listener) => tcp_listener.as_raw_fd(),
+        Listener::Unix(unix_listener) => unix_listener.as_raw_fd(),
+    }
+}
+
+pub fn get_port(listener: &Listener) -> Option<u16> {
+    match listener {
+        Listener::Tcp(tcp_listener) => Some(tcp_listener.local_addr().ok()?.port()),
+        _ => None,
+    }
+}
+
+pub fn get_socket_address(host_and_port: &str) -> Option<SocketAddr> {
+    match host_and_port.to_socket_addrs() {
+        Ok(mut addrs) => addrs.next(),
+        _ => None,
+    }
+}
+
+// ipv6 sockets only accept ipv6 so ipv4 and ipv6 listeners can share a port
+fn bind_tcp(host_and_port: &str) -> Result<Listener, String> {
+    let address = match get_socket_address(host_and_port) {
+        Some(addr) => addr,
+        _ => return Err(format!("{}: no socket address", host_and_port)),
+    };
+
+    let socket = match Socket::new(
+        Domain::for_address(address),
+        Type::STREAM,
+        Some(Protocol::TCP),
+    ) {
+        Ok(sckt) => sckt,
+        Err(e) => return Err(e.to_string()),
+    };
+
+    if address.is_ipv6() {
+        if let Err(e) = socket.set_only_v6(true) {
+            return Err(e.to_string());
+        }
+    }
+
+    if let Err(e) = socket.set_reuse_address(true) {
+        return Err(e.to_string());
+    }
+
+    if let Err(e) = socket.set_nonblocking(true) {
+        return Err(e.to_string());
+    }
+
+    if let Err(e) = socket.bind(&address.into()) {
+        return Err(format!("{}: {}", host_and_port, e));
+    }
+
+    if let Err(e) = socket.listen(BACKLOG) {
+        return Err(e.to_string());
+    }
+
+    match TcpListener::from_std(socket.into()) {
+        Ok(lstnr) => Ok(Listener::Tcp(lstnr)),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+fn bind_unix(
+    path: &Path,
+    mode: &Option<String>,
+    owner: &Option<String>,
+    group: &Option<String>,
+) -> Result<Listener, String> {
+    let mode = match mode {
+        Some(mode) => match u32::from_str_radix(mode.trim_start_matches("0o"), 8) {
+            Ok(m) => Some(m),
+            _ => return Err(format!("{}: mode is not octal", mode)),
+        },
+        _ => None,
+    };
+
+    remove_stale_socket(path)?;
+
+    // Only the owner can connect until mode and ownership are applied. The
+    // umask is process wide, files created meanwhile are only more restricted.
+    let bound = if mode.is_some() || owner.is_some() || group.is_some() {
+        // SAFETY: umask only swaps the file mode creation mask
+        let umask = unsafe { libc::umask(SOCKET_UMASK) };
+        let bound = UnixListener::bind(path);
+        // SAFETY: as above
+        unsafe { libc::umask(umask) };
+        bound
+    } else {
+        UnixListener::bind(path)
+    };
+
+    let listener = match bound {
+        Ok(lstnr) => lstnr,
+        Err(e) => return Err(format!("{}: {}", path.display(), e)),
+    };
+
+    let uid = match owner {
+        Some(owner) => Some(get_uid(owner)?),
+        _ => None,
+    };
+
+    let gid = match group {
+        Some(group) => Some(get_gid(group)?),
+        _ => None,
+    };
+
+    if uid.is_some() || gid.is_some() {
+        if let Err(e) = chown(path, uid, gid) {
+            return Err(format!("{}: {}", path.display(), e));
+        }
+    }
+
+    if let Some(mode) = mode {
+        if let Err(e) = fs::set_permissions(path, fs::Permissions::from_mode(mode)) {
+            return Err(format!("{}: {}", path.display(), e));
+        }
+    }
+
+    Ok(Listener::Unix(listener))
+}
+
+// sockets left behind by a previous run are replaced,
+// sockets with a live server behind them are not
+fn remove_stale_socket(path: &Path) -> Result<(), String> {
+    let metadata = match fs::symlink_metadata(path) {
+        Ok(m) => m,
+        Err(e) if io::ErrorKind::NotFound == e.kind() => return Ok(()),
+        Err(e) => return Err(format!("{}: {}", path.display(), e)),
+    };
+
+    if !metadata.file_type().is_socket() {
+        return Err(format!("{}: not a socket", path.display()));
+    }
+
+    if UnixStream::connect(path).is_ok() {
+        return Err(format!("{}: address already in use", path.display()));
+    }
+
+    match fs::remove_file(path) {
+        Ok(_) => Ok(()),
+        Err(e) => Err(format!("{}: {}", path.display(), e)),
+    }
+}
+
+// owners and groups are names or numeric ids
+fn get_uid(owner: &str) -> Result<u32, String> {
+    if let Ok(uid) = owner.parse() {
+        return Ok(uid);
+    }
+
+    let name = match CString::new(owner) {
+        Ok(n) => n,
+        Err(e) => return Err(e.to_string()),
+    };
+
+    // SAFETY: passwd only holds integers and pointers, all zeros is valid
+    let mut passwd: libc::passwd = unsafe { mem::zeroed() };
+    let mut buffer = vec![0; PASSWD_BUFFER_SIZE];
+    let mut result = ptr::null_mut();
+    // SAFETY: name is nul terminated, the buffer length matches the buffer
+    // and its strings are not read after the buffer is dropped
+    let rc = unsafe {
+        libc::getpwnam_r(
+            name.as_ptr(),
+            &mut passwd,
+            buffer.as_mut_ptr(),
+            buffer.len(),
+            &mut result,
+        )
+    };
+
+    match (rc, result.is_null()) {
+        (0, false) => Ok(passwd.pw_uid),
+        _ => Err(format!("{}: user not found", owner)),
+    }
+}
+
+fn get_gid(group: &str) -> Result<u32, String> {
+    if let Ok(gid) = group.parse() {
+        return Ok(gid);
+    }
+
+    let name = match CString::new(group) {
+        Ok(n) => n,
+        Err(e) => return Err(e.to_string()),
+    };
+
+    // SAFETY: group only holds integers and pointers, all zeros is valid
+    let mut grp: libc::group = unsafe { mem::zeroed() };
+    let mut buffer = vec![0; PASSWD_BUFFER_SIZE];
+    let mut result = ptr::null_mut();
+    // SAFETY: name is nul terminated, the buffer length matches the buffer
+    // and its strings are not read after the buffer is dropped
+    let rc = unsafe {
+        libc::getgrnam_r(
+            name.as_ptr(),
+            &mut grp,
+            buffer.as_mut_ptr(),
+            buffer.len(),
+            &mut result,
+        )
+    };
+
+    match (rc, result.is_null()) {
+        (0, false) => Ok(grp.gr_gid),
+        _ => Err(format!("{}: group not found", group)),
+    }
+}
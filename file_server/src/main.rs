@@ -1,50 +1,547 @@
+use hyper::header::HeaderValue;
 use hyper_util::rt::{TokioExecutor, TokioIo};
 use hyper_util::server::conn::auto::Builder;
+use quinn::Endpoint;
 use std::env;
+use std::future::Future;
+use std::io;
+use std::net::UdpSocket;
+use std::os::fd::{AsRawFd, RawFd};
 use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite};
 use tokio::net::TcpListener;
+use tokio::runtime;
+use tokio::sync::Notify;
+use tokio::task::JoinSet;
+use tokio::time;
+use tokio_rustls::TlsAcceptor;
+use tokio_util::sync::CancellationToken;
+use tokio_util::task::TaskTracker;
 
+mod accept;
+mod acme;
+mod acme_client;
 mod config;
+mod counters;
+mod dev_tls;
+mod http3;
+mod listener;
+mod precompress;
+mod redirect;
 mod service;
+mod shutdown;
+mod systemd;
+mod tls;
+mod tls_reload;
+mod upgrade;
 
-use crate::config::Config;
+use crate::config::{Config, Hsts, TlsConfig};
+use crate::listener::Listener;
+use crate::tls_reload::SharedServerConfig;
+use response::{AvailableEncodings, ClientRule, RangeLimits, ServiceRequirements};
 
-#[tokio::main]
-async fn main() -> Result<(), String> {
-    let conf = match get_config().await {
+fn main() -> Result<(), String> {
+    // sockets passed by systemd or an upgrading process are used instead of binding
+    let passed_fds = systemd::take_passed_fds();
+
+    let runtime = match runtime::Builder::new_multi_thread().enable_all().build() {
+        Ok(rt) => rt,
+        Err(e) => return Err(e.to_string()),
+    };
+
+    runtime.block_on(run(passed_fds))
+}
+
+async fn run(passed_fds: Vec<(RawFd, Option<String>)>) -> Result<(), String> {
+    // file_server --dev-tls [config]
+    let dev_tls = env::args().any(|arg| "--dev-tls" == arg);
+    let args: Vec<String> = env::args().filter(|arg| "--dev-tls" != arg).collect();
+
+    // file_server precompress [config]
+    if Some("precompress") == args.get(1).map(|arg| arg.as_str()) {
+        let conf = get_config(args.get(2)).await?;
+        return precompress::precompress(&conf).await;
+    }
+
+    let mut conf = match get_config(args.get(1)).await {
         Ok(c) => c,
         Err(e) => return Err(e),
     };
 
-    let listener = match TcpListener::bind(&conf.host_and_port).await {
-        Ok(lstnr) => lstnr,
-        Err(e) => return Err(e.to_string()),
+    if dev_tls {
+        conf.tls = Some(dev_tls::get_dev_tls_config(conf.tls).await?);
+    }
+
+    if let Some(tls) = &mut conf.tls {
+        acme::add_acme_certificates(tls).await?;
+    }
+
+    let listen_fds = systemd::get_listen_fds(passed_fds)?;
+
+    // listening sockets are handed to the new process on upgrade
+    let mut handoff_fds = Vec::new();
+
+    let quic_socket = match (listen_fds.http3, &conf.http3_host_and_port) {
+        (Some(socket), _) => Some(socket),
+        (_, Some(h3_host_and_port)) => Some(http3::bind(h3_host_and_port)?),
+        _ => None,
     };
 
-    println!("file_server: {}", conf.host_and_port);
+    // the endpoint gives up its socket on upgrade, a duplicate outlives
+    // it so the socket can still be handed over after a failed upgrade
+    let quic_handoff_socket = match quic_socket.as_ref().map(UdpSocket::try_clone) {
+        Some(Ok(socket)) => Some(socket),
+        Some(Err(e)) => return Err(e.to_string()),
+        _ => None,
+    };
 
-    let svc = service::Svc::new(conf.directory, conf.content_encodings, conf.filepath_404);
+    if let Some(socket) = &quic_handoff_socket {
+        handoff_fds.push((socket.as_raw_fd(), "http3".to_string()));
+    }
 
-    loop {
-        let (stream, _remote_address) = match listener.accept().await {
-            Ok(strm) => strm,
-            Err(e) => return Err(e.to_string()),
+    // http/3 is always encrypted
+    let quic_endpoint = match (quic_socket, &conf.tls) {
+        (Some(socket), Some(tls)) => {
+            let quic_server_config = tls::get_quic_server_config(tls).await?;
+            Some(http3::get_endpoint(socket, quic_server_config)?)
+        }
+        (Some(_), _) => return Err("http/3 requires tls".to_string()),
+        _ => None,
+    };
+
+    if conf.http_redirect.is_some() && conf.tls.is_none() {
+        return Err("http_redirect requires tls".to_string());
+    }
+
+    // reloads stop touching the quic endpoint once shutdown starts or while suspended
+    let shutdown = CancellationToken::new();
+    let quic_goaway = http3::get_shared_goaway(&shutdown);
+
+    let reload_notify = Arc::new(Notify::new());
+    let tls_config = match &conf.tls {
+        Some(tls) => {
+            let server_config = tls::get_server_config(tls).await?;
+            let shared = Arc::new(RwLock::new(server_config));
+            tls_reload::spawn_reloader(
+                tls.clone(),
+                shared.clone(),
+                quic_endpoint.clone(),
+                quic_goaway.clone(),
+                reload_notify.clone(),
+                shutdown.clone(),
+            )?;
+            Some(shared)
+        }
+        _ => None,
+    };
+
+    // keep-alive connections each hold a file descriptor
+    if let Some(max_open_files) = conf.max_open_files {
+        accept::raise_open_files_limit(max_open_files)?;
+    }
+
+    let mut listeners = listen_fds.listeners;
+    for (listener, _) in &listeners {
+        println!(
+            "file_server: {} inherited",
+            listener::get_local_name(listener)
+        );
+    }
+
+    if listeners.is_empty() {
+        for listener_config in &conf.listeners {
+            let is_tls = listener::is_tls(listener_config);
+            listeners.push((listener::bind(listener_config)?, is_tls));
+            println!("file_server: {}", listener::get_name(listener_config));
+        }
+    }
+
+    if listeners.is_empty() {
+        return Err("no listeners".to_string());
+    }
+
+    // the new process learns which listeners skip tls from their names
+    for (listener, is_tls) in &listeners {
+        let name = if *is_tls {
+            "listener"
+        } else {
+            systemd::PLAIN_FD_NAME
+        };
+        handoff_fds.push((listener::get_raw_fd(listener), name.to_string()));
+    }
+
+    // every connection is tracked so it can be drained on shutdown
+    let connections = TaskTracker::new();
+    shutdown::spawn_signal_handler(shutdown.clone())?;
+    let mut upgrade_signal = upgrade::get_upgrade_signal()?;
+
+    if let (Some(http_redirect), Some(challenge_dir)) =
+        (&conf.http_redirect, get_acme_challenge_directory(&conf))
+    {
+        let redirect_listener = match listen_fds.http_redirect {
+            Some(lstnr) => lstnr,
+            _ => match TcpListener::bind(&http_redirect.host_and_port).await {
+                Ok(lstnr) => lstnr,
+                Err(e) => return Err(e.to_string()),
+            },
+        };
+
+        handoff_fds.push((redirect_listener.as_raw_fd(), "http_redirect".to_string()));
+
+        // redirects point at the first tls tcp listener
+        let https_port = match listeners
+            .iter()
+            .filter(|(_, is_tls)| *is_tls)
+            .find_map(|(listener, _)| listener::get_port(listener))
+        {
+            Some(port) => port,
+            _ => return Err("http_redirect requires a tls tcp listener".to_string()),
         };
 
-        let io = TokioIo::new(stream);
-        let svc = svc.clone();
+        if let Ok(address) = redirect_listener.local_addr() {
+            println!("file_server: {} redirects to https", address);
+        }
 
+        let redirect_svc = service::Svc::new(get_challenge_requirements(challenge_dir))
+            .with_https_redirect(https_port);
+        let shutdown = shutdown.clone();
+        let connections = connections.clone();
         tokio::task::spawn(async move {
-            // log service errors here
-            Builder::new(TokioExecutor::new())
-                .serve_connection(io, svc)
-                .await
+            let served = serve_listener(
+                || redirect_listener.accept(),
+                redirect_svc,
+                None,
+                shutdown,
+                connections,
+            )
+            .await;
+
+            if let Err(e) = served {
+                println!("file_server: redirect listener stopped: {}", e);
+            }
+        });
+    }
+
+    // certificates are ordered once the listeners can answer challenges
+    if let Some(TlsConfig {
+        acme: Some(acme), ..
+    }) = &conf.tls
+    {
+        let challenge_dir = get_acme_challenge_directory(&conf);
+        acme::spawn_acme(acme.clone(), challenge_dir, reload_notify.clone())?;
+    }
+
+    // hsts is only sent over https
+    let hsts = match &conf.tls {
+        Some(_) => get_hsts(&conf.hsts),
+        _ => None,
+    };
+
+    let svc = service::Svc::new(ServiceRequirements {
+        directory: conf.directory,
+        available_encodings: AvailableEncodings::new(
+            &conf.content_encodings,
+            &conf.encoding_extensions,
+        ),
+        fallback_404: conf.filepath_404,
+        range_limits: conf.range_limits.unwrap_or_default(),
+        content_location: conf.content_location.unwrap_or(false),
+        decompress_fallback: conf.decompress_fallback.unwrap_or(false),
+        skip_stale_encodings: conf.skip_stale_encodings.unwrap_or(false),
+        use_as_dictionary: conf.use_as_dictionary,
+        compression_cache: conf.compression_cache,
+        client_rules: get_client_rules(&conf.tls),
+    })
+    .with_hsts(hsts);
+
+    if let Some(endpoint) = &quic_endpoint {
+        if let Ok(address) = endpoint.local_addr() {
+            println!("file_server: {} http/3", address);
+        }
+        tokio::task::spawn(http3::serve(
+            endpoint.clone(),
+            svc.clone(),
+            quic_goaway.clone(),
+            shutdown.clone(),
+            connections.clone(),
+        ));
+    }
+
+    // tcp responses advertise http/3
+    let svc = svc.with_alt_svc(get_alt_svc(&quic_endpoint));
+
+    let drain_timeout = match conf.drain_timeout {
+        Some(secs) => Duration::from_secs(secs),
+        _ => shutdown::DRAIN_TIMEOUT,
+    };
+
+    // every listener feeds the same service
+    let mut listener_tasks = JoinSet::new();
+    // hsts and alt-svc only apply to https origins
+    // https://www.rfc-editor.org/rfc/rfc6797#section-7.2
+    for (listener, is_tls) in listeners {
+        let (svc, tls_config) = if is_tls {
+            (svc.clone(), tls_config.clone())
+        } else {
+            (svc.clone().with_hsts(None).with_alt_svc(None), None)
+        };
+        let shutdown = shutdown.clone();
+        let connections = connections.clone();
+        listener_tasks.spawn(async move {
+            match listener {
+                Listener::Tcp(tcp_listener) => {
+                    let accept_fn = || tcp_listener.accept();
+                    serve_listener(accept_fn, svc, tls_config, shutdown, connections).await
+                }
+                Listener::Unix(unix_listener) => {
+                    let accept_fn = || unix_listener.accept();
+                    serve_listener(accept_fn, svc, tls_config, shutdown, connections).await
+                }
+            }
         });
     }
+
+    systemd::notify("READY=1");
+    systemd::spawn_watchdog();
+    counters::spawn_reporter();
+    upgrade::notify_ready();
+
+    // a broken listener shuts everything down
+    let mut upgraded = false;
+    let served = loop {
+        tokio::select! {
+            _ = shutdown.cancelled() => break Ok(()),
+            Some(joined) = listener_tasks.join_next() => break match joined {
+                Ok(served) => served,
+                Err(e) => Err(e.to_string()),
+            },
+            _ = upgrade_signal.recv() => {
+                println!("file_server: upgrading");
+                let quic = match (&quic_endpoint, &quic_handoff_socket, &conf.tls) {
+                    (Some(endpoint), Some(socket), Some(tls)) => {
+                        Some((endpoint, socket, tls, &quic_goaway))
+                    }
+                    _ => None,
+                };
+                match upgrade(&handoff_fds, quic, &shutdown).await {
+                    Ok(pid) => {
+                        println!("file_server: upgraded to {}", pid);
+                        systemd::notify(&format!("MAINPID={}", pid));
+                        upgraded = true;
+                        break Ok(());
+                    }
+                    Err(e) => println!("file_server: upgrade failed: {}", e),
+                }
+            }
+        }
+    };
+    shutdown.cancel();
+
+    // systemd follows the new process after an upgrade
+    if !upgraded {
+        systemd::notify("STOPPING=1");
+    }
+
+    // no new connections are accepted while draining
+    while listener_tasks.join_next().await.is_some() {}
+
+    counters::report();
+
+    let drained = shutdown::drain(connections, drain_timeout).await;
+
+    if let Some(endpoint) = quic_endpoint {
+        http3::close(&endpoint);
+    }
+
+    served.and(drained)
+}
+
+// The old process keeps serving until the new one is ready. HTTP/3 is
+// served again when the new process doesn't take over, otherwise tcp
+// responses would keep advertising it.
+async fn upgrade(
+    handoff_fds: &[(RawFd, String)],
+    quic: Option<(&Endpoint, &UdpSocket, &TlsConfig, &http3::SharedGoaway)>,
+    shutdown: &CancellationToken,
+) -> Result<u32, String> {
+    let handoff_fds = upgrade::get_handoff_fds(handoff_fds)?;
+
+    let (endpoint, handoff_socket, tls, goaway) = match quic {
+        Some(quic) => quic,
+        _ => return upgrade::start_new_process(handoff_fds).await,
+    };
+
+    let upgraded = match http3::suspend(endpoint, goaway, shutdown).await {
+        Ok(_) => upgrade::start_new_process(handoff_fds).await,
+        Err(e) => Err(e),
+    };
+
+    // a draining process keeps http/3 off
+    if upgraded.is_ok() || shutdown.is_cancelled() {
+        return upgraded;
+    }
+
+    let resumed = match tls::get_quic_server_config(tls).await {
+        Ok(quic_server_config) => {
+            http3::resume(endpoint, handoff_socket, goaway, quic_server_config)
+        }
+        Err(e) => Err(e),
+    };
+
+    if let Err(e) = resumed {
+        println!("file_server: http/3 not resumed: {}", e);
+    }
+
+    upgraded
+}
+
+async fn serve_listener<F, Fut, S, A>(
+    accept_fn: F,
+    svc: service::Svc,
+    tls_config: Option<SharedServerConfig>,
+    shutdown: CancellationToken,
+    connections: TaskTracker,
+) -> Result<(), String>
+where
+    F: Fn() -> Fut,
+    Fut: Future<Output = io::Result<(S, A)>>,
+    S: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+{
+    loop {
+        let accepted = tokio::select! {
+            accepted = accept::accept(&accept_fn) => accepted,
+            _ = shutdown.cancelled() => return Ok(()),
+        };
+
+        let (stream, _remote_address) = match accepted {
+            Ok(strm) => strm,
+            Err(e) => return Err(e),
+        };
+
+        let tls_acceptor = tls_config
+            .as_ref()
+            .and_then(tls_reload::get_current_server_config)
+            .map(TlsAcceptor::from);
+
+        connections.spawn(serve_accepted(
+            stream,
+            svc.clone(),
+            tls_acceptor,
+            shutdown.clone(),
+        ));
+    }
+}
+
+async fn serve_accepted<S>(
+    stream: S,
+    svc: service::Svc,
+    tls_acceptor: Option<TlsAcceptor>,
+    shutdown: CancellationToken,
+) where
+    S: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+{
+    let acceptor = match tls_acceptor {
+        Some(acceptor) => acceptor,
+        _ => return serve_connection(stream, svc, shutdown).await,
+    };
+
+    // failed and stalled handshakes are the client's problem
+    if let Ok(Ok(tls_stream)) = time::timeout(tls::HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await
+    {
+        // tls-alpn-01 validation ends with the handshake
+        if Some(acme::ACME_TLS_ALPN) == tls_stream.get_ref().1.alpn_protocol() {
+            return;
+        }
+
+        let client_names = tls::get_client_names(tls_stream.get_ref().1.peer_certificates());
+        serve_connection(tls_stream, svc.with_client_names(client_names), shutdown).await;
+    }
+}
+
+async fn serve_connection<S>(stream: S, svc: service::Svc, shutdown: CancellationToken)
+where
+    S: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+{
+    let builder = Builder::new(TokioExecutor::new());
+    let connection = builder.serve_connection(TokioIo::new(stream), svc);
+    tokio::pin!(connection);
+
+    // log service errors here
+    tokio::select! {
+        _ = connection.as_mut() => return,
+        _ = shutdown.cancelled() => {}
+    }
+
+    // in-flight requests finish, idle connections close right away
+    connection.as_mut().graceful_shutdown();
+    let _ = connection.await;
+}
+
+// https://www.rfc-editor.org/rfc/rfc7838
+fn get_alt_svc(quic_endpoint: &Option<Endpoint>) -> Option<HeaderValue> {
+    let port = match quic_endpoint {
+        Some(endpoint) => endpoint.local_addr().ok()?.port(),
+        _ => return None,
+    };
+
+    HeaderValue::from_str(&format!("h3=\":{}\"; ma=86400", port)).ok()
+}
+
+// challenge files are served by the redirect listener
+fn get_acme_challenge_directory(conf: &Config) -> Option<PathBuf> {
+    let http_redirect = conf.http_redirect.as_ref()?;
+    match &http_redirect.acme_challenge_directory {
+        Some(challenge_dir) => Some(challenge_dir.clone()),
+        _ => Some(conf.directory.clone()),
+    }
+}
+
+// the redirect listener only serves ACME challenge files
+fn get_challenge_requirements(challenge_dir: PathBuf) -> ServiceRequirements {
+    ServiceRequirements {
+        directory: challenge_dir,
+        available_encodings: AvailableEncodings::default(),
+        fallback_404: None,
+        range_limits: RangeLimits::default(),
+        content_location: false,
+        decompress_fallback: false,
+        skip_stale_encodings: false,
+        use_as_dictionary: None,
+        compression_cache: None,
+        client_rules: Vec::new(),
+    }
+}
+
+fn get_hsts(hsts: &Option<Hsts>) -> Option<HeaderValue> {
+    let hsts = hsts.as_ref()?;
+
+    let mut directives = format!("max-age={}", hsts.max_age);
+    if hsts.include_subdomains.unwrap_or(false) {
+        directives.push_str("; includeSubDomains");
+    }
+    if hsts.preload.unwrap_or(false) {
+        directives.push_str("; preload");
+    }
+
+    HeaderValue::from_str(&directives).ok()
+}
+
+fn get_client_rules(tls: &Option<TlsConfig>) -> Vec<ClientRule> {
+    let client_auth = match tls {
+        Some(TlsConfig {
+            client_auth: Some(ca),
+            ..
+        }) => ca,
+        _ => return Vec::new(),
+    };
+
+    client_auth.rules.clone().unwrap_or_default()
 }
 
-async fn get_config() -> Result<Config, String> {
-    match env::args().nth(1) {
+async fn get_config(conf_path: Option<&String>) -> Result<Config, String> {
+    match conf_path {
         Some(conf_path) => {
             let conf_path_buf = PathBuf::from(conf_path);
             return Config::try_from(&conf_path_buf).await;
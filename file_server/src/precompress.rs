@@ -0,0 +1,360 @@
+use response::{add_extension, encode_file, has_encoder, AvailableEncodings, Quality};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::fs::Metadata;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::thread;
+use std::time::UNIX_EPOCH;
+use tokio::fs;
+use tokio::io;
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+
+use crate::config::Config;
+
+// compressing tiny files costs more in headers than it saves
+const MIN_FILE_SIZE: u64 = 256;
+// encoded files must be at most 90% of the original
+const MAX_RATIO_NUMERATOR: u64 = 9;
+const MAX_RATIO_DENOMINATOR: u64 = 10;
+
+// formats that are already compressed
+const SKIPPED_EXTENSIONS: [&str; 24] = [
+    "7z", "aac", "avif", "br", "bz2", "dcb", "dcz", "flac", "gif", "gz", "ico", "jpeg", "jpg",
+    "mp3", "mp4", "ogg", "png", "webm", "webp", "woff", "woff2", "xz", "zip", "zst",
+];
+
+// an original that did not shrink enough as it was when it was tried
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+struct Rejected {
+    modified: u64,
+    size: u64,
+}
+
+enum Outcome {
+    Written,
+    UpToDate,
+    NotWorthIt,
+    Rejected(Rejected),
+}
+
+pub async fn precompress(conf: &Config) -> Result<(), String> {
+    let available_encodings =
+        AvailableEncodings::new(&conf.content_encodings, &conf.encoding_extensions);
+
+    let encodings = get_precompress_encodings(&available_encodings);
+    if encodings.is_empty() {
+        return Err("content_encodings has no supported encodings to precompress".to_string());
+    }
+
+    let mut filepaths = Vec::new();
+    collect_filepaths(&conf.directory, &available_encodings, &mut filepaths).await?;
+
+    let parallelism = match thread::available_parallelism() {
+        Ok(p) => p.get(),
+        _ => 1,
+    };
+    let semaphore = Arc::new(Semaphore::new(parallelism));
+
+    let record_path = get_record_path(&conf.directory);
+    let record = match &record_path {
+        Some(rp) => read_record(rp).await,
+        _ => BTreeMap::new(),
+    };
+
+    let mut tasks = JoinSet::new();
+    for filepath in filepaths {
+        for (encoding, extension) in &encodings {
+            let permit = match semaphore.clone().acquire_owned().await {
+                Ok(p) => p,
+                Err(e) => return Err(e.to_string()),
+            };
+
+            let record_key = get_record_key(&conf.directory, &filepath, extension);
+            let rejected = record.get(&record_key).cloned();
+
+            let filepath = filepath.clone();
+            let encoding = encoding.clone();
+            let extension = extension.clone();
+            tasks.spawn(async move {
+                let outcome = precompress_file(&filepath, &encoding, &extension, rejected).await;
+                drop(permit);
+                (filepath, encoding, record_key, outcome)
+            });
+        }
+    }
+
+    let mut next_record = BTreeMap::new();
+    let (mut written, mut up_to_date, mut not_worth_it, mut failed) = (0, 0, 0, 0);
+    while let Some(joined) = tasks.join_next().await {
+        let (filepath, encoding, record_key, outcome) = match joined {
+            Ok(j) => j,
+            Err(e) => return Err(e.to_string()),
+        };
+
+        match outcome {
+            Ok(Outcome::Written) => written += 1,
+            Ok(Outcome::UpToDate) => up_to_date += 1,
+            Ok(Outcome::NotWorthIt) => not_worth_it += 1,
+            Ok(Outcome::Rejected(rejected)) => {
+                not_worth_it += 1;
+                next_record.insert(record_key, rejected);
+            }
+            Err(e) => {
+                failed += 1;
+                println!(
+                    "file_server: failed to precompress {} with {}: {}",
+                    filepath.display(),
+                    encoding,
+                    e
+                );
+            }
+        }
+    }
+
+    println!(
+        "file_server: precompressed {} written, {} up to date, {} not worth compressing, {} failed",
+        written, up_to_date, not_worth_it, failed
+    );
+
+    if let Some(rp) = &record_path {
+        if let Err(e) = write_record(rp, &next_record).await {
+            println!("file_server: failed to write {}: {}", rp.display(), e);
+        }
+    }
+
+    if 0 < failed {
+        return Err(format!("{} files failed to precompress", failed));
+    }
+
+    Ok(())
+}
+
+// encoded files are written with the first suffix of an encoding
+fn get_precompress_encodings(available_encodings: &AvailableEncodings) -> Vec<(String, String)> {
+    let mut encodings = Vec::new();
+    for encoding in available_encodings.get_encodings() {
+        // dictionary compressed files need a dictionary and are built elsewhere
+        if !has_encoder(encoding) {
+            continue;
+        }
+
+        if let Some(extension) = available_encodings.get_extensions(encoding).first() {
+            encodings.push((encoding.to_string(), extension.clone()));
+        }
+    }
+
+    encodings
+}
+
+async fn collect_filepaths(
+    directory: &Path,
+    available_encodings: &AvailableEncodings,
+    filepaths: &mut Vec<PathBuf>,
+) -> Result<(), String> {
+    let mut pending = vec![directory.to_path_buf()];
+
+    while let Some(dir) = pending.pop() {
+        let mut entries = match fs::read_dir(&dir).await {
+            Ok(e) => e,
+            Err(e) => return Err(format!("{}: {}", dir.display(), e)),
+        };
+
+        loop {
+            let entry = match entries.next_entry().await {
+                Ok(Some(e)) => e,
+                Ok(None) => break,
+                Err(e) => return Err(format!("{}: {}", dir.display(), e)),
+            };
+
+            // symlinks are not followed to avoid cycles
+            let file_type = match entry.file_type().await {
+                Ok(ft) => ft,
+                Err(e) => return Err(format!("{}: {}", entry.path().display(), e)),
+            };
+
+            let path = entry.path();
+            if file_type.is_dir() {
+                pending.push(path);
+                continue;
+            }
+
+            if file_type.is_file() && is_original(&path, available_encodings) {
+                filepaths.push(path);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// encoded siblings and temporary files left by an interrupted run
+// are not originals
+fn is_original(filepath: &Path, available_encodings: &AvailableEncodings) -> bool {
+    let filename = match filepath.file_name().and_then(|name| name.to_str()) {
+        Some(name) => name.to_ascii_lowercase(),
+        _ => return false,
+    };
+
+    let filename = match filename.strip_suffix(".tmp") {
+        Some(name) => name,
+        _ => &filename,
+    };
+
+    for encoding in available_encodings.get_encodings() {
+        for enc_ext in available_encodings.get_extensions(encoding) {
+            if filename.ends_with(&enc_ext.to_ascii_lowercase()) {
+                return false;
+            }
+        }
+    }
+
+    true
+}
+
+// encoded siblings of skipped originals are still cleaned up
+fn is_compressible(filepath: &Path) -> bool {
+    let extension = match filepath.extension().and_then(|ext| ext.to_str()) {
+        Some(ext) => ext.to_ascii_lowercase(),
+        _ => return true,
+    };
+
+    !SKIPPED_EXTENSIONS.contains(&extension.as_str())
+}
+
+async fn precompress_file(
+    filepath: &Path,
+    encoding: &str,
+    extension: &str,
+    rejected: Option<Rejected>,
+) -> Result<Outcome, String> {
+    let encoded_path = add_extension(filepath, extension);
+
+    let source_metadata = match fs::metadata(filepath).await {
+        Ok(m) => m,
+        Err(e) => return Err(e.to_string()),
+    };
+
+    // incremental: encoded files newer than their source are kept
+    if let Ok(encoded_metadata) = fs::metadata(&encoded_path).await {
+        if let (Ok(source_mtime), Ok(encoded_mtime)) =
+            (source_metadata.modified(), encoded_metadata.modified())
+        {
+            if source_mtime <= encoded_mtime {
+                return Ok(Outcome::UpToDate);
+            }
+        }
+    }
+
+    if source_metadata.len() < MIN_FILE_SIZE || !is_compressible(filepath) {
+        remove_stale_encoded_file(&encoded_path).await?;
+        return Ok(Outcome::NotWorthIt);
+    }
+
+    // originals that did not shrink enough are tried again once they change
+    let original = get_rejected(&source_metadata);
+    if Some(&original) == rejected.as_ref() {
+        remove_stale_encoded_file(&encoded_path).await?;
+        return Ok(Outcome::Rejected(original));
+    }
+
+    // write to a temporary sibling so a half written file is never served
+    let mut tmp_name = OsString::from(encoded_path.as_os_str());
+    tmp_name.push(".tmp");
+    let tmp_path = PathBuf::from(tmp_name);
+
+    let encoded_size = match encode_file(encoding, Quality::Best, filepath, &tmp_path).await {
+        Ok(size) => size,
+        Err(e) => {
+            let _ = fs::remove_file(&tmp_path).await;
+            return Err(e);
+        }
+    };
+
+    if source_metadata.len() * MAX_RATIO_NUMERATOR < encoded_size * MAX_RATIO_DENOMINATOR {
+        let _ = fs::remove_file(&tmp_path).await;
+        remove_stale_encoded_file(&encoded_path).await?;
+        return Ok(Outcome::Rejected(original));
+    }
+
+    if let Err(e) = fs::rename(&tmp_path, &encoded_path).await {
+        let _ = fs::remove_file(&tmp_path).await;
+        return Err(e.to_string());
+    }
+
+    Ok(Outcome::Written)
+}
+
+// an outdated encoded file would otherwise keep being served
+async fn remove_stale_encoded_file(encoded_path: &Path) -> Result<(), String> {
+    match fs::remove_file(encoded_path).await {
+        Ok(_) => Ok(()),
+        Err(e) if io::ErrorKind::NotFound == e.kind() => Ok(()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+fn get_rejected(metadata: &Metadata) -> Rejected {
+    let modified = match metadata
+        .modified()
+        .map(|mtime| mtime.duration_since(UNIX_EPOCH))
+    {
+        Ok(Ok(since_epoch)) => since_epoch.as_nanos() as u64,
+        _ => 0,
+    };
+
+    Rejected {
+        modified,
+        size: metadata.len(),
+    }
+}
+
+// ./www -> ./www.precompress.json
+// rejected originals are recorded outside the directory so the record is never served
+fn get_record_path(directory: &Path) -> Option<PathBuf> {
+    let mut record_name = directory.file_name()?.to_os_string();
+    record_name.push(".precompress.json");
+
+    Some(directory.with_file_name(record_name))
+}
+
+fn get_record_key(directory: &Path, filepath: &Path, extension: &str) -> String {
+    let relative_path = filepath.strip_prefix(directory).unwrap_or(filepath);
+    add_extension(relative_path, extension)
+        .to_string_lossy()
+        .to_string()
+}
+
+// a missing or unreadable record means every original is tried
+async fn read_record(record_path: &Path) -> BTreeMap<String, Rejected> {
+    let record_json = match fs::read_to_string(record_path).await {
+        Ok(r) => r,
+        _ => return BTreeMap::new(),
+    };
+
+    serde_json::from_str(&record_json).unwrap_or_default()
+}
+
+async fn write_record(
+    record_path: &Path,
+    record: &BTreeMap<String, Rejected>,
+) -> Result<(), String> {
+    if record.is_empty() {
+        return match fs::remove_file(record_path).await {
+            Err(e) if io::ErrorKind::NotFound != e.kind() => Err(e.to_string()),
+            _ => Ok(()),
+        };
+    }
+
+    let record_json = match serde_json::to_string_pretty(record) {
+        Ok(j) => j,
+        Err(e) => return Err(e.to_string()),
+    };
+
+    match fs::write(record_path, record_json).await {
+        Ok(_) => Ok(()),
+        Err(e) => Err(e.to_string()),
+    }
+}
@@ -0,0 +1,74 @@
+use http_body_util::{BodyExt, Empty, Full};
+use hyper::header::{HeaderValue, CONTENT_LENGTH, CONTENT_TYPE, HOST, LOCATION};
+use hyper::http::uri::Authority;
+use hyper::http::{Request, Response};
+use hyper::StatusCode;
+use std::str::FromStr;
+
+use response::{get_normalized_path, BoxedResponse};
+
+// https://www.rfc-editor.org/rfc/rfc8555#section-8.3
+const ACME_CHALLENGE_PATH: &str = "/.well-known/acme-challenge/";
+const BAD_REQUEST_400: &str = "400 bad request";
+const HTTPS_PORT: u16 = 443;
+
+// challenge responses are served over plain HTTP, everything else moves to HTTPS
+pub fn is_acme_challenge<B>(req: &Request<B>) -> bool {
+    get_normalized_path(req.uri().path()).starts_with(ACME_CHALLENGE_PATH)
+}
+
+// 308 keeps the request method
+pub fn build_https_redirect_response<B>(
+    req: &Request<B>,
+    https_port: u16,
+) -> Result<BoxedResponse, hyper::http::Error> {
+    let host = match get_host(req) {
+        Some(host) => host,
+        _ => return build_bad_request_response(),
+    };
+
+    let path_and_query = match req.uri().path_and_query() {
+        Some(pq) => pq.as_str(),
+        _ => "/",
+    };
+
+    let location = match https_port {
+        HTTPS_PORT => format!("https://{}{}", host, path_and_query),
+        _ => format!("https://{}:{}{}", host, https_port, path_and_query),
+    };
+
+    Response::builder()
+        .status(StatusCode::PERMANENT_REDIRECT)
+        .header(LOCATION, location)
+        .header(CONTENT_LENGTH, 0)
+        .body(Empty::new().map_err(|e| match e {}).boxed())
+}
+
+// absolute-form request targets take precedence over the host header
+// https://www.rfc-editor.org/rfc/rfc9112#section-3.2.2
+fn get_host<B>(req: &Request<B>) -> Option<String> {
+    if let Some(authority) = req.uri().authority() {
+        return Some(authority.host().to_string());
+    }
+
+    let host = req.headers().get(HOST)?.to_str().ok()?;
+    match Authority::from_str(host) {
+        Ok(authority) => Some(authority.host().to_string()),
+        _ => None,
+    }
+}
+
+fn build_bad_request_response() -> Result<BoxedResponse, hyper::http::Error> {
+    Response::builder()
+        .status(StatusCode::BAD_REQUEST)
+        .header(
+            CONTENT_TYPE,
+            HeaderValue::from_static("text/html; charset=utf-8"),
+        )
+        .header(CONTENT_LENGTH, BAD_REQUEST_400.len())
+        .body(
+            Full::new(bytes::Bytes::from(BAD_REQUEST_400))
+                .map_err(|e| match e {})
+                .boxed(),
+        )
+}
@@ -1,8 +1,7 @@
-use hyper::body::Incoming as IncomingBody;
+use hyper::header::{HeaderValue, ALT_SVC, STRICT_TRANSPORT_SECURITY};
 use hyper::service::Service;
 use hyper::Request;
 use std::future::Future;
-use std::path::PathBuf;
 use std::pin::Pin;
 
 /*
@@ -10,41 +9,88 @@ use std::pin::Pin;
     It should work with hyper responses across
     different libraries and dependencies.
 */
-use response::{build_response, BoxedResponse};
+use response::{build_response, BoxedResponse, ClientNames, ServiceRequirements};
+
+use crate::redirect::{build_https_redirect_response, is_acme_challenge};
 
 #[derive(Clone, Debug)]
 pub struct Svc {
-    directory: PathBuf,
-    content_encodings: Option<Vec<String>>,
-    fallback_404: Option<PathBuf>,
+    requirements: ServiceRequirements,
+    client_names: Option<ClientNames>,
+    alt_svc: Option<HeaderValue>,
+    hsts: Option<HeaderValue>,
+    https_port: Option<u16>,
 }
 
 impl Svc {
-    pub fn new(
-        directory: PathBuf,
-        content_encodings: Option<Vec<String>>,
-        fallback_404: Option<PathBuf>,
-    ) -> Svc {
+    pub fn new(requirements: ServiceRequirements) -> Svc {
         Svc {
-            directory: directory,
-            content_encodings: content_encodings,
-            fallback_404: fallback_404,
+            requirements,
+            client_names: None,
+            alt_svc: None,
+            hsts: None,
+            https_port: None,
         }
     }
+
+    // advertises alternative services like HTTP/3 on every response
+    pub fn with_alt_svc(mut self, alt_svc: Option<HeaderValue>) -> Svc {
+        self.alt_svc = alt_svc;
+        self
+    }
+
+    // only send over HTTPS
+    // https://www.rfc-editor.org/rfc/rfc6797#section-7.2
+    pub fn with_hsts(mut self, hsts: Option<HeaderValue>) -> Svc {
+        self.hsts = hsts;
+        self
+    }
+
+    // redirects everything except ACME challenges to HTTPS on the given port
+    pub fn with_https_redirect(mut self, https_port: u16) -> Svc {
+        self.https_port = Some(https_port);
+        self
+    }
+
+    // names of the client certificate of a connection
+    pub fn with_client_names(mut self, client_names: Option<ClientNames>) -> Svc {
+        self.client_names = client_names;
+        self
+    }
 }
 
-impl Service<Request<IncomingBody>> for Svc {
+// request bodies are never read, so HTTP/3 requests work as well
+impl<B: Send + 'static> Service<Request<B>> for Svc {
     type Response = BoxedResponse;
     type Error = hyper::http::Error;
     type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
 
-    fn call(&self, req: Request<IncomingBody>) -> Self::Future {
-        let directory = self.directory.clone();
-        let content_encodings = self.content_encodings.clone();
-        let fallback_404 = self.fallback_404.clone();
+    fn call(&self, mut req: Request<B>) -> Self::Future {
+        let requirements = self.requirements.clone();
+        let alt_svc = self.alt_svc.clone();
+        let hsts = self.hsts.clone();
+
+        if let Some(https_port) = self.https_port {
+            if !is_acme_challenge(&req) {
+                let res = build_https_redirect_response(&req, https_port);
+                return Box::pin(async move { res });
+            }
+        }
+
+        if let Some(client_names) = &self.client_names {
+            req.extensions_mut().insert(client_names.clone());
+        }
+
+        Box::pin(async move {
+            let mut res = build_response(req, requirements).await?;
+            if let Some(alt_svc) = alt_svc {
+                res.headers_mut().insert(ALT_SVC, alt_svc);
+            }
+            if let Some(hsts) = hsts {
+                res.headers_mut().insert(STRICT_TRANSPORT_SECURITY, hsts);
+            }
 
-        Box::pin(
-            async move { build_response(req, directory, content_encodings, fallback_404).await },
-        )
+            Ok(res)
+        })
     }
 }
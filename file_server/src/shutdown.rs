@@ -0,0 +1,53 @@
+use std::time::Duration;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::time;
+use tokio_util::sync::CancellationToken;
+use tokio_util::task::TaskTracker;
+
+// enough for most downloads to finish, short enough for rollouts
+pub const DRAIN_TIMEOUT: Duration = Duration::from_secs(30);
+
+// SIGTERM and SIGINT stop accepting connections and start draining.
+pub fn spawn_signal_handler(shutdown: CancellationToken) -> Result<(), String> {
+    let mut sigterm = match signal(SignalKind::terminate()) {
+        Ok(s) => s,
+        Err(e) => return Err(e.to_string()),
+    };
+
+    let mut sigint = match signal(SignalKind::interrupt()) {
+        Ok(s) => s,
+        Err(e) => return Err(e.to_string()),
+    };
+
+    tokio::spawn(async move {
+        tokio::select! {
+            _ = sigterm.recv() => {}
+            _ = sigint.recv() => {}
+        }
+
+        println!("file_server: shutting down");
+        shutdown.cancel();
+    });
+
+    Ok(())
+}
+
+// Connections finish their in-flight requests until the drain timeout.
+// Connections still open after it are cut off and reported as an error.
+pub async fn drain(connections: TaskTracker, drain_timeout: Duration) -> Result<(), String> {
+    connections.close();
+
+    if time::timeout(drain_timeout, connections.wait())
+        .await
+        .is_err()
+    {
+        return Err(format!(
+            "drain timeout elapsed, {} connections cut off",
+            connections.len()
+        ));
+    }
+
+    println!("file_server: all connections drained");
+
+    Ok(())
+}
@@ -0,0 +1,208 @@
+use socket2::{SockRef, Socket, Type};
+use std::cmp;
+use std::env;
+use std::net::UdpSocket;
+use std::os::fd::{BorrowedFd, FromRawFd, RawFd};
+use std::os::unix::net::{SocketAddr, UnixDatagram};
+use std::process;
+use std::time::Duration;
+use tokio::net::{TcpListener, UnixListener};
+use tokio::time;
+
+use crate::listener::Listener;
+use crate::upgrade;
+
+// https://www.freedesktop.org/software/systemd/man/latest/sd_listen_fds.html
+const SD_LISTEN_FDS_START: RawFd = 3;
+const HTTP_REDIRECT_FD_NAME: &str = "http_redirect";
+pub const PLAIN_FD_NAME: &str = "plain";
+const MIN_WATCHDOG_INTERVAL: Duration = Duration::from_millis(10);
+
+// Passed sockets replace the configured addresses. A stream socket named
+// http_redirect serves redirects, one named plain skips tls and a datagram
+// socket serves http/3. Listeners are paired with whether they speak tls.
+pub struct ListenFds {
+    pub listeners: Vec<(Listener, bool)>,
+    pub http_redirect: Option<TcpListener>,
+    pub http3: Option<UdpSocket>,
+}
+
+// Returns the passed descriptors with their names. The variables are
+// removed so child processes don't take the sockets for their own. Changing
+// the environment races with other threads, so this runs before the runtime.
+pub fn take_passed_fds() -> Vec<(RawFd, Option<String>)> {
+    let listen_pid = env::var("LISTEN_PID").ok();
+    let listen_fd_count = env::var("LISTEN_FDS").ok();
+    let listen_fd_names = env::var("LISTEN_FDNAMES").ok();
+
+    env::remove_var("LISTEN_FDS");
+    env::remove_var("LISTEN_PID");
+    env::remove_var("LISTEN_FDNAMES");
+
+    // sockets are meant for the process systemd or an upgrade started
+    if Some(process::id().to_string()) != listen_pid && !upgrade::is_upgrade() {
+        return Vec::new();
+    }
+
+    let fd_count: RawFd = match listen_fd_count.map(|fds| fds.parse()) {
+        Some(Ok(count)) => count,
+        _ => return Vec::new(),
+    };
+
+    let fd_names: Vec<String> = match listen_fd_names {
+        Some(names) => names.split(':').map(|name| name.to_string()).collect(),
+        _ => Vec::new(),
+    };
+
+    let mut passed_fds = Vec::new();
+    for index in 0..fd_count {
+        let name = fd_names.get(index as usize).cloned();
+        passed_fds.push((SD_LISTEN_FDS_START + index, name));
+    }
+
+    passed_fds
+}
+
+pub fn get_listen_fds(passed_fds: Vec<(RawFd, Option<String>)>) -> Result<ListenFds, String> {
+    let mut listen_fds = ListenFds {
+        listeners: Vec::new(),
+        http_redirect: None,
+        http3: None,
+    };
+
+    for (fd, name) in passed_fds {
+        let name = name.as_deref();
+
+        // descriptors that were never passed may belong to the runtime by now
+        // SAFETY: LISTEN_FDS says fd is open and nothing else owns it yet,
+        // the borrow ends before the descriptor is taken over below
+        let borrowed_fd = unsafe { BorrowedFd::borrow_raw(fd) };
+        let socket_type = match SockRef::from(&borrowed_fd).r#type() {
+            Ok(t) => t,
+            Err(e) => return Err(format!("fd {}: {}", fd, e)),
+        };
+
+        // inherited sockets must not leak into other processes
+        // SAFETY: fcntl only changes flags of an open descriptor
+        if -1 == unsafe { libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) } {
+            return Err(format!("fd {}: {}", fd, std::io::Error::last_os_error()));
+        }
+
+        // SAFETY: fd is an open socket and this is its only owner,
+        // every passed index is taken over exactly once
+        let socket = unsafe { Socket::from_raw_fd(fd) };
+        if let Err(e) = socket.set_nonblocking(true) {
+            return Err(format!("fd {}: {}", fd, e));
+        }
+
+        if Type::DGRAM == socket_type {
+            if listen_fds.http3.is_some() {
+                return Err(format!("fd {}: more than one datagram socket", fd));
+            }
+            listen_fds.http3 = Some(socket.into());
+            continue;
+        }
+
+        let listener = from_socket(socket)?;
+        match (name, listener) {
+            (Some(HTTP_REDIRECT_FD_NAME), Listener::Tcp(tcp_listener)) => {
+                listen_fds.http_redirect = Some(tcp_listener);
+            }
+            (Some(PLAIN_FD_NAME), listener) => listen_fds.listeners.push((listener, false)),
+            (_, listener) => listen_fds.listeners.push((listener, true)),
+        }
+    }
+
+    Ok(listen_fds)
+}
+
+fn from_socket(socket: Socket) -> Result<Listener, String> {
+    let address = match socket.local_addr() {
+        Ok(addr) => addr,
+        Err(e) => return Err(e.to_string()),
+    };
+
+    if address.is_unix() {
+        return match UnixListener::from_std(socket.into()) {
+            Ok(lstnr) => Ok(Listener::Unix(lstnr)),
+            Err(e) => Err(e.to_string()),
+        };
+    }
+
+    match TcpListener::from_std(socket.into()) {
+        Ok(lstnr) => Ok(Listener::Tcp(lstnr)),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+// https://www.freedesktop.org/software/systemd/man/latest/sd_notify.html
+pub fn notify(state: &str) {
+    let notify_socket = match env::var("NOTIFY_SOCKET") {
+        Ok(path) => path,
+        _ => return,
+    };
+
+    if let Err(e) = send_notification(&notify_socket, state) {
+        println!("file_server: sd_notify failed: {}", e);
+    }
+}
+
+fn send_notification(notify_socket: &str, state: &str) -> Result<(), String> {
+    let address = match get_notify_address(notify_socket) {
+        Ok(addr) => addr,
+        Err(e) => return Err(e.to_string()),
+    };
+
+    let socket = match UnixDatagram::unbound() {
+        Ok(sckt) => sckt,
+        Err(e) => return Err(e.to_string()),
+    };
+
+    match socket.send_to_addr(state.as_bytes(), &address) {
+        Ok(_) => Ok(()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+// sockets starting with @ live in the abstract namespace
+#[cfg(target_os = "linux")]
+fn get_notify_address(notify_socket: &str) -> std::io::Result<SocketAddr> {
+    use std::os::linux::net::SocketAddrExt;
+
+    match notify_socket.strip_prefix('@') {
+        Some(name) => SocketAddr::from_abstract_name(name),
+        _ => SocketAddr::from_pathname(notify_socket),
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn get_notify_address(notify_socket: &str) -> std::io::Result<SocketAddr> {
+    SocketAddr::from_pathname(notify_socket)
+}
+
+// Pings at half the watchdog interval until the process exits.
+// A zero interval disables the watchdog, tiny ones are clamped.
+pub fn spawn_watchdog() {
+    let watchdog_usec: u64 = match env::var("WATCHDOG_USEC").ok().map(|usec| usec.parse()) {
+        Some(Ok(0)) => return,
+        Some(Ok(usec)) => usec,
+        _ => return,
+    };
+
+    if let Ok(watchdog_pid) = env::var("WATCHDOG_PID") {
+        if process::id().to_string() != watchdog_pid {
+            return;
+        }
+    }
+
+    let interval = cmp::max(
+        Duration::from_micros(watchdog_usec / 2),
+        MIN_WATCHDOG_INTERVAL,
+    );
+    tokio::spawn(async move {
+        loop {
+            notify("WATCHDOG=1");
+            time::sleep(interval).await;
+        }
+    });
+}
@@ -0,0 +1,287 @@
+use quinn::crypto::rustls::QuicServerConfig;
+use response::ClientNames;
+use rustls::crypto::ring::sign::any_supported_type;
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::server::danger::ClientCertVerifier;
+use rustls::server::{ClientHello, ResolvesServerCert, WebPkiClientVerifier};
+use rustls::sign::CertifiedKey;
+use rustls::version::TLS13;
+use rustls::{ConfigBuilder, RootCertStore, ServerConfig, WantsVerifier};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::fs;
+use tokio::io::AsyncWriteExt;
+use x509_parser::extensions::GeneralName;
+use x509_parser::prelude::parse_x509_certificate;
+
+use crate::acme::{get_tls_alpn_certified_key, ACME_TLS_ALPN};
+use crate::config::{ClientAuth, TlsCertificate, TlsConfig};
+
+// clients that stall mid handshake would otherwise hold a connection forever
+pub const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+pub async fn get_server_config(tls: &TlsConfig) -> Result<Arc<ServerConfig>, String> {
+    let mut server_config = build_server_config(tls, ServerConfig::builder()).await?;
+
+    // hyper_util's auto builder serves both
+    server_config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    if tls.acme.is_some() {
+        server_config.alpn_protocols.push(ACME_TLS_ALPN.to_vec());
+    }
+
+    Ok(Arc::new(server_config))
+}
+
+// QUIC requires TLS 1.3
+pub async fn get_quic_server_config(tls: &TlsConfig) -> Result<quinn::ServerConfig, String> {
+    let builder = ServerConfig::builder_with_protocol_versions(&[&TLS13]);
+    let mut server_config = build_server_config(tls, builder).await?;
+
+    server_config.alpn_protocols = vec![b"h3".to_vec()];
+
+    match QuicServerConfig::try_from(server_config) {
+        Ok(qsc) => Ok(quinn::ServerConfig::with_crypto(Arc::new(qsc))),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+async fn build_server_config(
+    tls: &TlsConfig,
+    builder: ConfigBuilder<ServerConfig, WantsVerifier>,
+) -> Result<ServerConfig, String> {
+    let resolver = Arc::new(SniResolver::try_from(tls).await?);
+
+    let server_config = match &tls.client_auth {
+        Some(client_auth) => builder
+            .with_client_cert_verifier(get_client_verifier(client_auth).await?)
+            .with_cert_resolver(resolver),
+        _ => builder.with_no_client_auth().with_cert_resolver(resolver),
+    };
+
+    Ok(server_config)
+}
+
+// Without required, clients may connect without a certificate and only
+// paths protected by client rules are refused.
+async fn get_client_verifier(
+    client_auth: &ClientAuth,
+) -> Result<Arc<dyn ClientCertVerifier>, String> {
+    let mut roots = RootCertStore::empty();
+    for cert in get_certs(&client_auth.ca).await? {
+        if let Err(e) = roots.add(cert) {
+            return Err(format!("{}: {}", client_auth.ca.display(), e));
+        }
+    }
+
+    let mut builder = WebPkiClientVerifier::builder(Arc::new(roots));
+    if !client_auth.required.unwrap_or(false) {
+        builder = builder.allow_unauthenticated();
+    }
+
+    match builder.build() {
+        Ok(verifier) => Ok(verifier),
+        Err(e) => Err(format!("{}: {}", client_auth.ca.display(), e)),
+    }
+}
+
+// common name and subject alternative names of a verified client certificate
+pub fn get_client_names(peer_certificates: Option<&[CertificateDer]>) -> Option<ClientNames> {
+    let cert = peer_certificates?.first()?;
+
+    let (_, x509) = match parse_x509_certificate(cert) {
+        Ok(x) => x,
+        _ => return None,
+    };
+
+    let mut names = Vec::new();
+    for cn in x509.subject().iter_common_name() {
+        if let Ok(name) = cn.as_str() {
+            names.push(name.to_string());
+        }
+    }
+
+    if let Ok(Some(san)) = x509.subject_alternative_name() {
+        for general_name in &san.value.general_names {
+            match general_name {
+                GeneralName::DNSName(name) => names.push(name.to_string()),
+                GeneralName::RFC822Name(name) => names.push(name.to_string()),
+                GeneralName::URI(name) => names.push(name.to_string()),
+                _ => {}
+            }
+        }
+    }
+
+    Some(ClientNames(names))
+}
+
+// Certificates are selected by the names they are valid for. Clients
+// without SNI or with an unknown name get the first certificate.
+#[derive(Debug)]
+struct SniResolver {
+    certified_keys: Vec<(Vec<String>, Arc<CertifiedKey>)>,
+}
+
+impl SniResolver {
+    async fn try_from(tls: &TlsConfig) -> Result<SniResolver, String> {
+        if tls.certificates.is_empty() {
+            return Err("tls has no certificates".to_string());
+        }
+
+        let mut certified_keys = Vec::with_capacity(tls.certificates.len());
+        for certificate in &tls.certificates {
+            certified_keys.push(get_certified_key(certificate).await?);
+        }
+
+        Ok(SniResolver { certified_keys })
+    }
+}
+
+impl ResolvesServerCert for SniResolver {
+    fn resolve(&self, client_hello: ClientHello) -> Option<Arc<CertifiedKey>> {
+        // https://www.rfc-editor.org/rfc/rfc8737#section-3
+        if let Some(mut protocols) = client_hello.alpn() {
+            if protocols.any(|protocol| ACME_TLS_ALPN == protocol) {
+                return get_tls_alpn_certified_key(client_hello.server_name()?);
+            }
+        }
+
+        if let Some(server_name) = client_hello.server_name() {
+            for (names, certified_key) in &self.certified_keys {
+                if names.iter().any(|name| name_matches(name, server_name)) {
+                    return Some(certified_key.clone());
+                }
+            }
+        }
+
+        self.certified_keys.first().map(|(_, ck)| ck.clone())
+    }
+}
+
+async fn get_certified_key(
+    certificate: &TlsCertificate,
+) -> Result<(Vec<String>, Arc<CertifiedKey>), String> {
+    let certs = get_certs(&certificate.cert).await?;
+    let key = get_key(&certificate.key).await?;
+
+    let signing_key = match any_supported_type(&key) {
+        Ok(sk) => sk,
+        Err(e) => return Err(format!("{}: {}", certificate.key.display(), e)),
+    };
+
+    let names = match certs.first() {
+        Some(cert) => get_names(cert),
+        _ => return Err(format!("{}: no certificates", certificate.cert.display())),
+    };
+
+    let certified_key = CertifiedKey::new(certs, signing_key);
+    if let Err(e) = certified_key.keys_match() {
+        return Err(format!(
+            "{} does not match {}: {}",
+            certificate.key.display(),
+            certificate.cert.display(),
+            e
+        ));
+    }
+
+    Ok((names, Arc::new(certified_key)))
+}
+
+// expired or unreadable certificates have no time left
+pub async fn get_time_to_expiration(filepath: &Path) -> Option<Duration> {
+    let certs = get_certs(filepath).await.ok()?;
+    let (_, x509) = parse_x509_certificate(certs.first()?).ok()?;
+
+    x509.validity()
+        .time_to_expiration()
+        .map(|remaining| remaining.unsigned_abs())
+}
+
+pub async fn get_certs(filepath: &Path) -> Result<Vec<CertificateDer<'static>>, String> {
+    let pem = match fs::read(filepath).await {
+        Ok(p) => p,
+        Err(e) => return Err(format!("{}: {}", filepath.display(), e)),
+    };
+
+    let mut certs = Vec::new();
+    for cert in CertificateDer::pem_slice_iter(&pem) {
+        match cert {
+            Ok(c) => certs.push(c),
+            Err(e) => return Err(format!("{}: {}", filepath.display(), e)),
+        }
+    }
+
+    Ok(certs)
+}
+
+async fn get_key(filepath: &Path) -> Result<PrivateKeyDer<'static>, String> {
+    let pem = match fs::read(filepath).await {
+        Ok(p) => p,
+        Err(e) => return Err(format!("{}: {}", filepath.display(), e)),
+    };
+
+    match PrivateKeyDer::from_pem_slice(&pem) {
+        Ok(key) => Ok(key),
+        Err(e) => Err(format!("{}: {}", filepath.display(), e)),
+    }
+}
+
+// private keys are only readable by their owner
+pub async fn write_key(filepath: &Path, pem: &str) -> Result<(), String> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true).mode(0o600);
+
+    let mut file = match options.open(filepath).await {
+        Ok(file) => file,
+        Err(e) => return Err(e.to_string()),
+    };
+
+    match file.write_all(pem.as_bytes()).await {
+        Ok(_) => Ok(()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+// dns names of the subject alternative name extension,
+// or the common name of certificates without one
+fn get_names(cert: &CertificateDer) -> Vec<String> {
+    let mut names = Vec::new();
+
+    let (_, x509) = match parse_x509_certificate(cert) {
+        Ok(x) => x,
+        _ => return names,
+    };
+
+    if let Ok(Some(san)) = x509.subject_alternative_name() {
+        for general_name in &san.value.general_names {
+            if let GeneralName::DNSName(dns_name) = general_name {
+                names.push(dns_name.to_ascii_lowercase());
+            }
+        }
+    }
+
+    if names.is_empty() {
+        for cn in x509.subject().iter_common_name() {
+            if let Ok(name) = cn.as_str() {
+                names.push(name.to_ascii_lowercase());
+            }
+        }
+    }
+
+    names
+}
+
+// wildcards match exactly one label: *.example.com matches a.example.com
+fn name_matches(name: &str, server_name: &str) -> bool {
+    let server_name = server_name.to_ascii_lowercase();
+
+    if let Some(suffix) = name.strip_prefix("*.") {
+        return match server_name.split_once(".") {
+            Some((_, rest)) => rest == suffix,
+            _ => false,
+        };
+    }
+
+    name == server_name
+}
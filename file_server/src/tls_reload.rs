@@ -0,0 +1,131 @@
+use quinn::Endpoint;
+use rustls::ServerConfig;
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
+use tokio::fs;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::Notify;
+use tokio::time;
+use tokio_util::sync::CancellationToken;
+
+use crate::config::TlsConfig;
+use crate::http3::{set_server_config, SharedGoaway};
+use crate::tls::{get_quic_server_config, get_server_config};
+
+// certificate rotation is rare, a slow poll is enough
+const POLL_INTERVAL: Duration = Duration::from_secs(10);
+
+// New handshakes read the current config. Established connections keep
+// the config they were accepted with.
+pub type SharedServerConfig = Arc<RwLock<Arc<ServerConfig>>>;
+
+pub fn get_current_server_config(shared: &SharedServerConfig) -> Option<Arc<ServerConfig>> {
+    match shared.read() {
+        Ok(server_config) => Some(server_config.clone()),
+        _ => None,
+    }
+}
+
+// Reloads certificates when their files change, on SIGHUP, or when
+// notified. A failed reload keeps the previous certificates.
+pub fn spawn_reloader(
+    tls: TlsConfig,
+    shared: SharedServerConfig,
+    quic_endpoint: Option<Endpoint>,
+    quic_goaway: SharedGoaway,
+    reload_notify: Arc<Notify>,
+    shutdown: CancellationToken,
+) -> Result<(), String> {
+    let mut sighup = match signal(SignalKind::hangup()) {
+        Ok(s) => s,
+        Err(e) => return Err(e.to_string()),
+    };
+
+    let mut filepaths = Vec::new();
+    for certificate in &tls.certificates {
+        filepaths.push(certificate.cert.clone());
+        filepaths.push(certificate.key.clone());
+    }
+
+    if let Some(client_auth) = &tls.client_auth {
+        filepaths.push(client_auth.ca.clone());
+    }
+
+    tokio::spawn(async move {
+        let mut mtimes = get_mtimes(&filepaths).await;
+        let mut interval = time::interval(POLL_INTERVAL);
+
+        loop {
+            tokio::select! {
+                _ = interval.tick() => {
+                    let curr_mtimes = get_mtimes(&filepaths).await;
+                    if curr_mtimes == mtimes {
+                        continue;
+                    }
+                    mtimes = curr_mtimes;
+                }
+                _ = sighup.recv() => {
+                    mtimes = get_mtimes(&filepaths).await;
+                }
+                _ = reload_notify.notified() => {
+                    mtimes = get_mtimes(&filepaths).await;
+                }
+            }
+
+            reload(&tls, &shared, &quic_endpoint, &quic_goaway, &shutdown).await;
+        }
+    });
+
+    Ok(())
+}
+
+async fn reload(
+    tls: &TlsConfig,
+    shared: &SharedServerConfig,
+    quic_endpoint: &Option<Endpoint>,
+    quic_goaway: &SharedGoaway,
+    shutdown: &CancellationToken,
+) {
+    let server_config = match get_server_config(tls).await {
+        Ok(sc) => sc,
+        Err(e) => {
+            println!("file_server: failed to reload tls certificates: {}", e);
+            return;
+        }
+    };
+
+    // a draining or suspended endpoint refuses new connections and must keep doing so
+    if let Some(endpoint) = quic_endpoint {
+        let reloaded = match get_quic_server_config(tls).await {
+            Ok(_) if shutdown.is_cancelled() => Ok(()),
+            Ok(qsc) => set_server_config(endpoint, quic_goaway, qsc),
+            Err(e) => Err(e),
+        };
+        if let Err(e) = reloaded {
+            println!("file_server: failed to reload tls certificates: {}", e);
+            return;
+        }
+    }
+
+    match shared.write() {
+        Ok(mut current) => {
+            *current = server_config;
+            println!("file_server: reloaded tls certificates");
+        }
+        _ => println!("file_server: failed to reload tls certificates: lock poisoned"),
+    }
+}
+
+async fn get_mtimes(filepaths: &[PathBuf]) -> Vec<Option<SystemTime>> {
+    let mut mtimes = Vec::with_capacity(filepaths.len());
+    for filepath in filepaths {
+        let mtime = match fs::metadata(filepath).await {
+            Ok(m) => m.modified().ok(),
+            _ => None,
+        };
+        mtimes.push(mtime);
+    }
+
+    mtimes
+}
@@ -0,0 +1,179 @@
+use std::env;
+use std::io;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::os::unix::process::parent_id;
+use std::process;
+use std::time::Duration;
+use tokio::io::AsyncReadExt;
+use tokio::net::unix::pipe::Receiver;
+use tokio::process::Command;
+use tokio::signal::unix::{signal, Signal, SignalKind};
+use tokio::time;
+
+// the new process finds its sockets like systemd passes them
+// https://www.freedesktop.org/software/systemd/man/latest/sd_listen_fds.html
+const SD_LISTEN_FDS_START: RawFd = 3;
+const READY_TIMEOUT: Duration = Duration::from_secs(30);
+const READY_FD_ENV: &str = "FILE_SERVER_READY_FD";
+const UPGRADE_PID_ENV: &str = "FILE_SERVER_UPGRADE_PID";
+
+// listening sockets duplicated for the new process
+pub struct HandoffFds {
+    fds: Vec<OwnedFd>,
+    names: Vec<String>,
+}
+
+pub fn get_upgrade_signal() -> Result<Signal, String> {
+    match signal(SignalKind::user_defined2()) {
+        Ok(s) => Ok(s),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+// Duplicates land above the range they're moved into in the new process
+// so moving one never overwrites another.
+pub fn get_handoff_fds(listening_fds: &[(RawFd, String)]) -> Result<HandoffFds, String> {
+    let min_fd = SD_LISTEN_FDS_START + listening_fds.len() as RawFd + 1;
+
+    let mut handoff_fds = HandoffFds {
+        fds: Vec::new(),
+        names: Vec::new(),
+    };
+
+    for (fd, name) in listening_fds {
+        handoff_fds.fds.push(dup_above(*fd, min_fd)?);
+        handoff_fds.names.push(name.clone());
+    }
+
+    Ok(handoff_fds)
+}
+
+// The new process runs with the same arguments and gets the listening
+// sockets followed by the write end of a pipe. It writes to the pipe once
+// it accepts connections. Returns the pid of the new process.
+pub async fn start_new_process(handoff_fds: HandoffFds) -> Result<u32, String> {
+    let program = match env::args_os().next() {
+        Some(program) => program,
+        _ => return Err("no program name".to_string()),
+    };
+
+    let mut pipe_fds = [0; 2];
+    // SAFETY: pipe_fds has room for the two descriptors pipe2 writes
+    if -1 == unsafe { libc::pipe2(pipe_fds.as_mut_ptr(), libc::O_CLOEXEC) } {
+        return Err(io::Error::last_os_error().to_string());
+    }
+    // SAFETY: pipe2 succeeded so both descriptors are open and unowned
+    let ready_reader = unsafe { OwnedFd::from_raw_fd(pipe_fds[0]) };
+    // SAFETY: as above
+    let ready_writer = unsafe { OwnedFd::from_raw_fd(pipe_fds[1]) };
+
+    let HandoffFds { mut fds, names } = handoff_fds;
+    let fd_count = fds.len() as RawFd;
+    fds.push(dup_above(
+        ready_writer.as_raw_fd(),
+        SD_LISTEN_FDS_START + fd_count + 1,
+    )?);
+    drop(ready_writer);
+
+    let mut ready_receiver = match Receiver::from_owned_fd(ready_reader) {
+        Ok(receiver) => receiver,
+        Err(e) => return Err(e.to_string()),
+    };
+
+    let raw_fds: Vec<RawFd> = fds.iter().map(|fd| fd.as_raw_fd()).collect();
+    let mut command = Command::new(program);
+    command
+        .args(env::args_os().skip(1))
+        .env("LISTEN_FDS", fd_count.to_string())
+        .env("LISTEN_FDNAMES", names.join(":"))
+        .env_remove("LISTEN_PID")
+        .env_remove("WATCHDOG_PID")
+        .env(UPGRADE_PID_ENV, process::id().to_string())
+        .env(READY_FD_ENV, (SD_LISTEN_FDS_START + fd_count).to_string());
+
+    // dup2 clears close-on-exec on the moved descriptors
+    // SAFETY: the closure runs in the forked child and only calls dup2,
+    // which is async-signal-safe, on descriptors kept open by fds until
+    // spawn returns. raw_fds was allocated before the fork.
+    unsafe {
+        command.pre_exec(move || {
+            for (index, fd) in raw_fds.iter().enumerate() {
+                if -1 == libc::dup2(*fd, SD_LISTEN_FDS_START + index as RawFd) {
+                    return Err(io::Error::last_os_error());
+                }
+            }
+            Ok(())
+        });
+    }
+
+    let mut child = match command.spawn() {
+        Ok(child) => child,
+        Err(e) => return Err(e.to_string()),
+    };
+
+    // the new process holds the only write end now
+    drop(fds);
+
+    let mut ready = [0; 1];
+    let read = tokio::select! {
+        read = ready_receiver.read(&mut ready) => read,
+        _ = time::sleep(READY_TIMEOUT) => {
+            // the new process is reaped so it doesn't linger as a zombie
+            let _ = child.start_kill();
+            let _ = child.wait().await;
+            return Err("new process was not ready in time".to_string());
+        }
+    };
+
+    if let Ok(1) = read {
+        if let Some(pid) = child.id() {
+            return Ok(pid);
+        }
+    }
+
+    match child.wait().await {
+        Ok(status) => Err(format!(
+            "new process exited before it was ready: {}",
+            status
+        )),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+// only the process started by an upgrade has a parent waiting for it
+pub fn is_upgrade() -> bool {
+    match env::var(UPGRADE_PID_ENV) {
+        Ok(pid) => parent_id().to_string() == pid,
+        _ => false,
+    }
+}
+
+pub fn notify_ready() {
+    if !is_upgrade() {
+        return;
+    }
+
+    let ready_fd: RawFd = match env::var(READY_FD_ENV).ok().map(|fd| fd.parse()) {
+        Some(Ok(fd)) => fd,
+        _ => return,
+    };
+
+    // the descriptor is written and closed without taking ownership
+    // SAFETY: the parent passed ready_fd for this process only and nothing
+    // else in this process owns it, the buffer outlives the write
+    unsafe {
+        libc::write(ready_fd, [1u8].as_ptr().cast(), 1);
+        libc::close(ready_fd);
+    }
+}
+
+fn dup_above(fd: RawFd, min_fd: RawFd) -> Result<OwnedFd, String> {
+    // SAFETY: fcntl only reads fd and returns a new descriptor or -1
+    let dup_fd = unsafe { libc::fcntl(fd, libc::F_DUPFD_CLOEXEC, min_fd) };
+    if -1 == dup_fd {
+        return Err(io::Error::last_os_error().to_string());
+    }
+
+    // SAFETY: dup_fd is a fresh descriptor nothing else owns
+    Ok(unsafe { OwnedFd::from_raw_fd(dup_fd) })
+}
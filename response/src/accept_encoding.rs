@@ -0,0 +1,227 @@
+// https://www.rfc-editor.org/rfc/rfc9110#name-accept-encoding
+// https://www.rfc-editor.org/rfc/rfc9110#name-quality-values
+
+// q-values are kept as thousandths to avoid comparing floats
+pub const MAX_QVALUE: u16 = 1000;
+
+pub fn get_weighted_encodings(accept_encoding: &str) -> Vec<(String, u16)> {
+    let mut weighted_encodings = Vec::new();
+
+    for member in accept_encoding.split(",") {
+        let mut params = member.split(";");
+
+        let coding = match params.next() {
+            Some(c) => c.trim().to_ascii_lowercase(),
+            _ => continue,
+        };
+
+        if coding.is_empty() {
+            continue;
+        }
+
+        let mut qvalue = MAX_QVALUE;
+        for param in params {
+            let (name, value) = match param.split_once("=") {
+                Some(nv) => nv,
+                _ => continue,
+            };
+
+            if name.trim().eq_ignore_ascii_case("q") {
+                // an invalid q-value disqualifies the coding
+                qvalue = get_qvalue(value.trim()).unwrap_or(0);
+            }
+        }
+
+        // x-gzip is an alias of gzip
+        let coding = match coding.as_str() {
+            "x-gzip" => "gzip".to_string(),
+            _ => coding,
+        };
+
+        weighted_encodings.push((coding, qvalue));
+    }
+
+    weighted_encodings
+}
+
+pub fn get_encoding_qvalue(weighted_encodings: &[(String, u16)], encoding: &str) -> Option<u16> {
+    for (coding, qvalue) in weighted_encodings {
+        if coding == encoding {
+            return Some(*qvalue);
+        }
+    }
+
+    None
+}
+
+// qvalue = ( "0" [ "." 0*3DIGIT ] ) / ( "1" [ "." 0*3("0") ] )
+fn get_qvalue(qvalue_str: &str) -> Option<u16> {
+    let (whole, fraction) = match qvalue_str.split_once(".") {
+        Some(wf) => wf,
+        _ => (qvalue_str, ""),
+    };
+
+    if 3 < fraction.len() || !fraction.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+
+    let mut thousandths: u16 = 0;
+    for (index, digit) in fraction.bytes().enumerate() {
+        thousandths += (digit - b'0') as u16 * 10_u16.pow(2 - index as u32);
+    }
+
+    match whole {
+        "0" => Some(thousandths),
+        "1" if 0 == thousandths => Some(MAX_QVALUE),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hyper::header::ACCEPT_ENCODING;
+    use hyper::http::{Request, StatusCode};
+    use std::env;
+    use std::process;
+    use tokio::fs;
+
+    use crate::available_encodings::AvailableEncodings;
+    use crate::get_response::build_get_response;
+    use crate::range_response::RangeLimits;
+    use crate::response_paths::{get_encodings, identity_is_acceptable};
+    use crate::service_requirements::ServiceRequirements;
+
+    fn get_request(accept_encoding: &str) -> Request<()> {
+        Request::builder()
+            .uri("/index.html")
+            .header(ACCEPT_ENCODING, accept_encoding)
+            .body(())
+            .unwrap()
+    }
+
+    fn get_available_encodings() -> AvailableEncodings {
+        let encodings = vec!["br".to_string(), "zstd".to_string(), "gzip".to_string()];
+        AvailableEncodings::new(&Some(encodings), &None)
+    }
+
+    #[test]
+    fn parses_qvalues() {
+        assert_eq!(get_qvalue("1"), Some(1000));
+        assert_eq!(get_qvalue("1.000"), Some(1000));
+        assert_eq!(get_qvalue("0"), Some(0));
+        assert_eq!(get_qvalue("0.5"), Some(500));
+        assert_eq!(get_qvalue("0.125"), Some(125));
+        assert_eq!(get_qvalue("0."), Some(0));
+    }
+
+    #[test]
+    fn rejects_malformed_qvalues() {
+        for qvalue in [
+            "1.5", "1.001", "2", "0.1234", "-0.5", "0.a", ".5", "", "high",
+        ] {
+            assert_eq!(get_qvalue(qvalue), None, "{}", qvalue);
+        }
+    }
+
+    #[test]
+    fn malformed_qvalues_disqualify_the_coding() {
+        assert_eq!(
+            get_weighted_encodings("gzip;q=1.5, br;q=abc, zstd ; Q=0.8, X-GZIP"),
+            vec![
+                ("gzip".to_string(), 0),
+                ("br".to_string(), 0),
+                ("zstd".to_string(), 800),
+                ("gzip".to_string(), 1000),
+            ]
+        );
+    }
+
+    #[test]
+    fn excludes_codings_with_qvalue_zero() {
+        let req = get_request("br;q=0, gzip");
+        assert_eq!(
+            get_encodings(&req, &get_available_encodings()),
+            Some(vec!["gzip".to_string()])
+        );
+
+        let req = get_request("br;q=0, gzip;q=0");
+        assert_eq!(get_encodings(&req, &get_available_encodings()), None);
+    }
+
+    #[test]
+    fn wildcard_covers_unlisted_codings() {
+        let req = get_request("*");
+        assert_eq!(
+            get_encodings(&req, &get_available_encodings()),
+            Some(vec![
+                "br".to_string(),
+                "zstd".to_string(),
+                "gzip".to_string()
+            ])
+        );
+        assert!(identity_is_acceptable(&req));
+
+        let req = get_request("gzip, *;q=0");
+        assert_eq!(
+            get_encodings(&req, &get_available_encodings()),
+            Some(vec!["gzip".to_string()])
+        );
+        assert!(!identity_is_acceptable(&req));
+
+        let req = get_request("*;q=0, identity");
+        assert!(identity_is_acceptable(&req));
+    }
+
+    #[test]
+    fn ties_follow_server_preference() {
+        let req = get_request("gzip, zstd, br;q=0.5");
+        assert_eq!(
+            get_encodings(&req, &get_available_encodings()),
+            Some(vec![
+                "zstd".to_string(),
+                "gzip".to_string(),
+                "br".to_string()
+            ])
+        );
+
+        let req = get_request("gzip;q=0.8, br;q=0.8");
+        assert_eq!(
+            get_encodings(&req, &get_available_encodings()),
+            Some(vec!["br".to_string(), "gzip".to_string()])
+        );
+    }
+
+    #[tokio::test]
+    async fn refuses_identity_without_an_encoded_file() {
+        let test_dir = env::temp_dir().join(format!("response_identity_{}", process::id()));
+        let _ = fs::remove_dir_all(&test_dir).await;
+        fs::create_dir_all(&test_dir).await.unwrap();
+        fs::write(test_dir.join("index.html"), "<p>hello</p>")
+            .await
+            .unwrap();
+
+        let requirements = ServiceRequirements {
+            directory: test_dir.clone(),
+            available_encodings: get_available_encodings(),
+            fallback_404: None,
+            range_limits: RangeLimits::default(),
+            content_location: false,
+            decompress_fallback: false,
+            skip_stale_encodings: false,
+            use_as_dictionary: None,
+            compression_cache: None,
+            client_rules: Vec::new(),
+        };
+
+        let req = get_request("gzip, identity;q=0");
+        let res = build_get_response(&req, &requirements).await.unwrap();
+        assert_eq!(res.status(), StatusCode::NOT_ACCEPTABLE);
+
+        let req = get_request("gzip");
+        let res = build_get_response(&req, &requirements).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+
+        let _ = fs::remove_dir_all(&test_dir).await;
+    }
+}
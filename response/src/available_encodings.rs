@@ -1,56 +1,120 @@
-#[derive(Clone, Debug)]
+use std::collections::HashMap;
+
+// Encodings in server preference order, each with the file suffixes
+// its encoded files may have.
+#[derive(Clone, Debug, Default)]
 pub struct AvailableEncodings {
-    pub gzip: bool,
-    pub deflate: bool,
-    pub br: bool,
-    pub zstd: bool,
+    encodings: Vec<(String, Vec<String>)>,
 }
 
 impl AvailableEncodings {
-    pub fn new(potential_encodings: &Option<Vec<String>>) -> AvailableEncodings {
-        let mut av_enc = AvailableEncodings {
-            gzip: false,
-            deflate: false,
-            br: false,
-            zstd: false,
-        };
+    pub fn new(
+        potential_encodings: &Option<Vec<String>>,
+        encoding_extensions: &Option<HashMap<String, Vec<String>>>,
+    ) -> AvailableEncodings {
+        let mut av_enc = AvailableEncodings::default();
 
         if let Some(pe) = potential_encodings {
-            av_enc.update(pe);
+            av_enc.update(pe, encoding_extensions);
         }
 
         av_enc
     }
 
-    pub fn update(&mut self, potential_encodings: &Vec<String>) {
-        for encoding in potential_encodings {
-            match encoding.as_str() {
-                "gzip" => self.gzip = true,
-                "deflate" => self.deflate = true,
-                "br" => self.br = true,
-                "zstd" => self.zstd = true,
-                _ => {}
+    // encodings without a configured or default suffix are not available
+    pub fn update(
+        &mut self,
+        potential_encodings: &[String],
+        encoding_extensions: &Option<HashMap<String, Vec<String>>>,
+    ) {
+        for potential_encoding in potential_encodings {
+            let encoding = potential_encoding.trim().to_ascii_lowercase();
+            if "identity" == encoding || "*" == encoding || self.encoding_is_available(&encoding) {
+                continue;
+            }
+
+            let extensions = get_extensions(&encoding, encoding_extensions);
+            if !extensions.is_empty() {
+                self.encodings.push((encoding, extensions));
             }
         }
     }
 
+    pub fn any_available(&self) -> bool {
+        !self.encodings.is_empty()
+    }
+
+    pub fn any_dictionary_available(&self) -> bool {
+        self.encodings
+            .iter()
+            .any(|(encoding, _)| is_dictionary_encoding(encoding))
+    }
+
     pub fn encoding_is_available(&self, encoding: &str) -> bool {
-        match encoding {
-            "gzip" => self.gzip,
-            "deflate" => self.deflate,
-            "br" => self.br,
-            "zstd" => self.zstd,
-            _ => false,
+        self.encodings.iter().any(|(enc, _)| enc == encoding)
+    }
+
+    // server preference order
+    pub fn get_encodings(&self) -> impl Iterator<Item = &str> {
+        self.encodings.iter().map(|(encoding, _)| encoding.as_str())
+    }
+
+    pub fn get_extensions(&self, encoding: &str) -> &[String] {
+        for (enc, extensions) in &self.encodings {
+            if enc == encoding {
+                return extensions;
+            }
         }
+
+        &[]
     }
 }
 
-pub fn get_encoded_ext(encoding: &str) -> Option<&str> {
+fn get_extensions(
+    encoding: &str,
+    encoding_extensions: &Option<HashMap<String, Vec<String>>>,
+) -> Vec<String> {
+    let configured_extensions = encoding_extensions.as_ref().and_then(|ee| ee.get(encoding));
+
+    let mut extensions = Vec::new();
+    match configured_extensions {
+        Some(exts) => {
+            for ext in exts {
+                // suffixes may be written with or without a leading dot
+                let ext = match ext.starts_with(".") {
+                    true => ext.clone(),
+                    _ => ".".to_string() + ext,
+                };
+
+                if 1 < ext.len() && !extensions.contains(&ext) {
+                    extensions.push(ext);
+                }
+            }
+        }
+        _ => {
+            if let Some(ext) = get_default_ext(encoding) {
+                extensions.push(ext.to_string());
+            }
+        }
+    }
+
+    extensions
+}
+
+pub fn get_default_ext(encoding: &str) -> Option<&str> {
     match encoding {
         "gzip" => Some(".gz"),
         "zstd" => Some(".zst"),
         "br" => Some(".br"),
         "deflate" => Some(".zz"),
+        "dcb" => Some(".dcb"),
+        "dcz" => Some(".dcz"),
         _ => None,
     }
 }
+
+// dictionary compressed files are only served for a matching dictionary
+// https://www.rfc-editor.org/rfc/rfc9842
+pub fn is_dictionary_encoding(encoding: &str) -> bool {
+    matches!(encoding, "dcb" | "dcz")
+}
@@ -0,0 +1,131 @@
+use hyper::http::Request;
+use serde::{Deserialize, Serialize};
+
+use crate::response_paths::get_normalized_path;
+use crate::wildcard::wildcard_matches;
+
+// Requests for paths starting with path_prefix are only served to
+// clients whose certificate names match one of the client patterns.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ClientRule {
+    pub path_prefix: String,
+    pub clients: Vec<String>,
+}
+
+// Names of a verified client certificate, added to requests as an
+// extension by the server.
+#[derive(Clone, Debug, Default)]
+pub struct ClientNames(pub Vec<String>);
+
+pub fn client_is_authorized(req: &Request<()>, client_rules: &[ClientRule]) -> bool {
+    if client_rules.is_empty() {
+        return true;
+    }
+
+    // rules are checked against the same path files are resolved from
+    let path = get_normalized_path(req.uri().path());
+
+    let client_names = match req.extensions().get::<ClientNames>() {
+        Some(ClientNames(names)) => names.as_slice(),
+        _ => &[],
+    };
+
+    let mut protected = false;
+    for rule in client_rules {
+        if !path_has_prefix(&path, &rule.path_prefix) {
+            continue;
+        }
+
+        protected = true;
+        for pattern in &rule.clients {
+            // patterns only support "*" wildcards and ignore case
+            let pattern = pattern.to_ascii_lowercase();
+            if client_names.iter().any(|name| {
+                wildcard_matches(pattern.as_bytes(), name.to_ascii_lowercase().as_bytes())
+            }) {
+                return true;
+            }
+        }
+    }
+
+    !protected
+}
+
+// prefixes match whole segments: /internal matches /internal/a but not /internals
+fn path_has_prefix(path: &str, path_prefix: &str) -> bool {
+    let prefix = get_normalized_path(path_prefix);
+    if "/" == prefix {
+        return true;
+    }
+
+    match path.strip_prefix(&prefix) {
+        Some(rest) => rest.is_empty() || rest.starts_with("/"),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_request(path: &str, names: &[&str]) -> Request<()> {
+        let mut req = Request::builder().uri(path).body(()).unwrap();
+        let names = names.iter().map(|name| name.to_string()).collect();
+        req.extensions_mut().insert(ClientNames(names));
+        req
+    }
+
+    fn get_rules() -> Vec<ClientRule> {
+        vec![ClientRule {
+            path_prefix: "/private".to_string(),
+            clients: vec!["CI-*".to_string(), "laptop.example.com".to_string()],
+        }]
+    }
+
+    #[test]
+    fn prefixes_match_whole_segments() {
+        assert!(path_has_prefix("/private", "/private"));
+        assert!(path_has_prefix("/private/a.txt", "/private"));
+        assert!(!path_has_prefix("/privateer", "/private"));
+        assert!(!path_has_prefix("/public/private", "/private"));
+    }
+
+    #[test]
+    fn prefixes_ignore_trailing_slashes() {
+        assert!(path_has_prefix("/private", "/private/"));
+        assert!(path_has_prefix("/private/a.txt", "/private/"));
+        assert!(!path_has_prefix("/privateer", "/private/"));
+    }
+
+    #[test]
+    fn root_prefixes_match_every_path() {
+        assert!(path_has_prefix("/", "/"));
+        assert!(path_has_prefix("/a.txt", ""));
+    }
+
+    #[test]
+    fn authorizes_matching_clients_only() {
+        let rules = get_rules();
+
+        assert!(client_is_authorized(
+            &get_request("/private/a.txt", &["ci-7"]),
+            &rules
+        ));
+        assert!(client_is_authorized(
+            &get_request("/private/", &["Laptop.Example.com"]),
+            &rules
+        ));
+        assert!(!client_is_authorized(
+            &get_request("/private/a.txt", &["phone.example.com"]),
+            &rules
+        ));
+        assert!(!client_is_authorized(
+            &get_request("/public/../private/a.txt", &[]),
+            &rules
+        ));
+        assert!(client_is_authorized(
+            &get_request("/privateer", &[]),
+            &rules
+        ));
+    }
+}
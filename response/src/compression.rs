@@ -0,0 +1,93 @@
+use async_compression::tokio::bufread::{BrotliEncoder, GzipEncoder, ZlibEncoder, ZstdEncoder};
+use async_compression::Level;
+use std::path::Path;
+use tokio::fs::File;
+use tokio::io;
+use tokio::io::{AsyncRead, BufReader};
+use tokio::runtime::Handle;
+use tokio::task;
+
+// files compressed ahead of time favour size over speed
+const ZSTD_BEST_LEVEL: i32 = 19;
+// files compressed on request favour speed over size
+const GZIP_FAST_LEVEL: i32 = 6;
+const BROTLI_FAST_LEVEL: i32 = 5;
+const ZSTD_FAST_LEVEL: i32 = 3;
+
+#[derive(Clone, Copy)]
+pub enum Quality {
+    Best,
+    Fast,
+}
+
+pub fn has_encoder(encoding: &str) -> bool {
+    matches!(encoding, "gzip" | "deflate" | "br" | "zstd")
+}
+
+// deflate is the zlib format
+// https://www.rfc-editor.org/rfc/rfc9110#name-deflate-coding
+fn get_encoder(
+    encoding: &str,
+    quality: Quality,
+    file: File,
+) -> Option<Box<dyn AsyncRead + Send + Sync + Unpin>> {
+    let reader = BufReader::new(file);
+    let level = get_level(encoding, quality);
+
+    match encoding {
+        "gzip" => Some(Box::new(GzipEncoder::with_quality(reader, level))),
+        "deflate" => Some(Box::new(ZlibEncoder::with_quality(reader, level))),
+        "br" => Some(Box::new(BrotliEncoder::with_quality(reader, level))),
+        "zstd" => Some(Box::new(ZstdEncoder::with_quality(reader, level))),
+        _ => None,
+    }
+}
+
+fn get_level(encoding: &str, quality: Quality) -> Level {
+    match (encoding, quality) {
+        ("zstd", Quality::Best) => Level::Precise(ZSTD_BEST_LEVEL),
+        (_, Quality::Best) => Level::Best,
+        ("br", Quality::Fast) => Level::Precise(BROTLI_FAST_LEVEL),
+        ("zstd", Quality::Fast) => Level::Precise(ZSTD_FAST_LEVEL),
+        (_, Quality::Fast) => Level::Precise(GZIP_FAST_LEVEL),
+    }
+}
+
+// Encoding is cpu bound so it runs on the blocking pool instead of
+// stalling the runtime workers. Returns the size of the encoded file.
+pub async fn encode_file(
+    encoding: &str,
+    quality: Quality,
+    filepath: &Path,
+    encoded_path: &Path,
+) -> Result<u64, String> {
+    let source = match File::open(filepath).await {
+        Ok(f) => f,
+        Err(e) => return Err(e.to_string()),
+    };
+
+    let mut encoder = match get_encoder(encoding, quality, source) {
+        Some(enc) => enc,
+        _ => return Err("unsupported encoding".to_string()),
+    };
+
+    let mut encoded_file = match File::create(encoded_path).await {
+        Ok(f) => f,
+        Err(e) => return Err(e.to_string()),
+    };
+
+    let handle = Handle::current();
+    let encoded = task::spawn_blocking(move || {
+        handle.block_on(async move {
+            let encoded_size = io::copy(&mut encoder, &mut encoded_file).await?;
+            encoded_file.sync_all().await?;
+            Ok::<u64, io::Error>(encoded_size)
+        })
+    });
+
+    match encoded.await {
+        Ok(Ok(encoded_size)) => Ok(encoded_size),
+        Ok(Err(e)) => Err(e.to_string()),
+        Err(e) => Err(e.to_string()),
+    }
+}
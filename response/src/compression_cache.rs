@@ -0,0 +1,288 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::SystemTime;
+use tokio::fs;
+use tokio::io;
+use tokio::sync::Semaphore;
+
+use crate::compression::{encode_file, has_encoder, Quality};
+use crate::content_type::get_content_type;
+use crate::response_paths::add_extension;
+use crate::service_requirements::ServiceRequirements;
+
+// Files are compressed on their first request and written to a cache
+// directory outside of the served directory.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CompressionCache {
+    pub directory: PathBuf,
+    pub min_file_size: u64,
+    pub max_file_size: u64,
+    pub max_cache_size: u64,
+    pub content_types: Vec<String>,
+}
+
+// files currently being compressed
+static IN_FLIGHT: Mutex<BTreeSet<PathBuf>> = Mutex::new(BTreeSet::new());
+// one eviction walk at a time
+static EVICTING: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+// compression competes with serving requests for cpu time
+const MAX_COMPRESSIONS: usize = 2;
+static COMPRESSIONS: Semaphore = Semaphore::const_new(MAX_COMPRESSIONS);
+
+// ./www/docs/a.html -> ./cache/docs/a.html.gz
+pub fn get_cache_path(
+    requirements: &ServiceRequirements,
+    cache: &CompressionCache,
+    filepath: &Path,
+    encoding: &str,
+) -> Option<PathBuf> {
+    let relative_path = match filepath.strip_prefix(&requirements.directory) {
+        Ok(rp) => rp,
+        _ => return None,
+    };
+
+    let extension = requirements
+        .available_encodings
+        .get_extensions(encoding)
+        .first()?;
+
+    Some(add_extension(
+        &cache.directory.join(relative_path),
+        extension,
+    ))
+}
+
+// cached files older than their original are outdated
+pub async fn cache_is_fresh(filepath: &Path, cache_path: &Path) -> bool {
+    let original_mtime = match get_mtime(filepath).await {
+        Some(mtime) => mtime,
+        _ => return false,
+    };
+
+    match get_mtime(cache_path).await {
+        Some(mtime) => original_mtime <= mtime,
+        _ => false,
+    }
+}
+
+// Compresses in the background so the current request is not delayed.
+// Files are skipped while all compressions are busy and compressed on a
+// later request instead.
+pub fn spawn_compression(
+    requirements: &ServiceRequirements,
+    cache: &CompressionCache,
+    filepath: &Path,
+    encoding: &str,
+) {
+    if !has_encoder(encoding) || !content_type_is_allowed(cache, get_content_type(filepath)) {
+        return;
+    }
+
+    let cache_path = match get_cache_path(requirements, cache, filepath, encoding) {
+        Some(cp) => cp,
+        _ => return,
+    };
+
+    match IN_FLIGHT.lock() {
+        Ok(mut in_flight) => {
+            if !in_flight.insert(cache_path.clone()) {
+                return;
+            }
+        }
+        _ => return,
+    }
+
+    let permit = match COMPRESSIONS.try_acquire() {
+        Ok(p) => p,
+        _ => {
+            if let Ok(mut in_flight) = IN_FLIGHT.lock() {
+                in_flight.remove(&cache_path);
+            }
+            return;
+        }
+    };
+
+    let cache = cache.clone();
+    let cache_extensions = get_cache_extensions(requirements);
+    let filepath = filepath.to_path_buf();
+    let encoding = encoding.to_string();
+    tokio::spawn(async move {
+        let written = match compress_to_cache(&cache, &filepath, &cache_path, &encoding).await {
+            Ok(w) => w,
+            Err(e) => {
+                println!(
+                    "file_server: failed to cache {} with {}: {}",
+                    filepath.display(),
+                    encoding,
+                    e
+                );
+                false
+            }
+        };
+
+        drop(permit);
+        if let Ok(mut in_flight) = IN_FLIGHT.lock() {
+            in_flight.remove(&cache_path);
+        }
+
+        if written {
+            evict(&cache, &cache_extensions).await;
+        }
+    });
+}
+
+// cached files end with the first suffix of an encoding
+fn get_cache_extensions(requirements: &ServiceRequirements) -> Vec<String> {
+    let available_encodings = &requirements.available_encodings;
+
+    let mut extensions = Vec::new();
+    for encoding in available_encodings.get_encodings() {
+        if !has_encoder(encoding) {
+            continue;
+        }
+
+        if let Some(extension) = available_encodings.get_extensions(encoding).first() {
+            extensions.push(extension.clone());
+        }
+    }
+
+    extensions
+}
+
+// "text/*" allows every text type
+fn content_type_is_allowed(cache: &CompressionCache, content_type: &str) -> bool {
+    let essence = match content_type.split_once(";") {
+        Some((ess, _)) => ess.trim(),
+        _ => content_type.trim(),
+    };
+
+    for allowed in &cache.content_types {
+        if allowed.eq_ignore_ascii_case(essence) {
+            return true;
+        }
+
+        if let Some(prefix) = allowed.strip_suffix("/*") {
+            if let Some((media_type, _)) = essence.split_once("/") {
+                if prefix.eq_ignore_ascii_case(media_type) {
+                    return true;
+                }
+            }
+        }
+    }
+
+    false
+}
+
+async fn compress_to_cache(
+    cache: &CompressionCache,
+    filepath: &Path,
+    cache_path: &Path,
+    encoding: &str,
+) -> Result<bool, String> {
+    // encoded files may be deployed without an original
+    let metadata = match fs::metadata(filepath).await {
+        Ok(m) => m,
+        Err(e) if io::ErrorKind::NotFound == e.kind() => return Ok(false),
+        Err(e) => return Err(e.to_string()),
+    };
+
+    if metadata.len() < cache.min_file_size || cache.max_file_size < metadata.len() {
+        return Ok(false);
+    }
+
+    if let Some(parent) = cache_path.parent() {
+        if let Err(e) = fs::create_dir_all(parent).await {
+            return Err(e.to_string());
+        }
+    }
+
+    // write to a temporary sibling so a half written file is never served
+    let mut tmp_name = OsString::from(cache_path.as_os_str());
+    tmp_name.push(".tmp");
+    let tmp_path = PathBuf::from(tmp_name);
+
+    if let Err(e) = encode_file(encoding, Quality::Fast, filepath, &tmp_path).await {
+        let _ = fs::remove_file(&tmp_path).await;
+        return Err(e);
+    }
+
+    if let Err(e) = fs::rename(&tmp_path, cache_path).await {
+        let _ = fs::remove_file(&tmp_path).await;
+        return Err(e.to_string());
+    }
+
+    Ok(true)
+}
+
+// Oldest cached files are removed until the cache fits max_cache_size.
+// Only files the cache writes are removed, files still being written
+// and anything else in the directory are left alone.
+async fn evict(cache: &CompressionCache, cache_extensions: &[String]) {
+    let _evicting = EVICTING.lock().await;
+
+    let mut cached_files = Vec::new();
+    let mut pending = vec![cache.directory.clone()];
+    while let Some(dir) = pending.pop() {
+        let mut entries = match fs::read_dir(&dir).await {
+            Ok(e) => e,
+            _ => continue,
+        };
+
+        while let Ok(Some(entry)) = entries.next_entry().await {
+            let metadata = match entry.metadata().await {
+                Ok(m) => m,
+                _ => continue,
+            };
+
+            if metadata.is_dir() {
+                pending.push(entry.path());
+                continue;
+            }
+
+            if !metadata.is_file() || !is_cached_file(&entry.path(), cache_extensions) {
+                continue;
+            }
+
+            if let Ok(mtime) = metadata.modified() {
+                cached_files.push((mtime, metadata.len(), entry.path()));
+            }
+        }
+    }
+
+    let mut cache_size: u64 = cached_files.iter().map(|(_, len, _)| len).sum();
+    if cache_size <= cache.max_cache_size {
+        return;
+    }
+
+    cached_files.sort();
+    for (_, len, path) in cached_files {
+        if cache_size <= cache.max_cache_size {
+            break;
+        }
+
+        if fs::remove_file(&path).await.is_ok() {
+            cache_size -= len;
+        }
+    }
+}
+
+fn is_cached_file(path: &Path, cache_extensions: &[String]) -> bool {
+    let filename = match path.file_name().and_then(|name| name.to_str()) {
+        Some(name) => name,
+        _ => return false,
+    };
+
+    cache_extensions
+        .iter()
+        .any(|extension| filename.ends_with(extension.as_str()))
+}
+
+async fn get_mtime(filepath: &Path) -> Option<SystemTime> {
+    match fs::metadata(filepath).await {
+        Ok(m) => m.modified().ok(),
+        _ => None,
+    }
+}
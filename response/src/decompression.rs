@@ -0,0 +1,21 @@
+use async_compression::tokio::bufread::{BrotliDecoder, GzipDecoder, ZlibDecoder, ZstdDecoder};
+use tokio::fs::File;
+use tokio::io::{AsyncRead, BufReader};
+
+pub fn has_decoder(encoding: &str) -> bool {
+    matches!(encoding, "gzip" | "deflate" | "br" | "zstd")
+}
+
+// deflate is the zlib format
+// https://www.rfc-editor.org/rfc/rfc9110#name-deflate-coding
+pub fn get_decoder(encoding: &str, file: File) -> Option<Box<dyn AsyncRead + Send + Sync + Unpin>> {
+    let reader = BufReader::new(file);
+
+    match encoding {
+        "gzip" => Some(Box::new(GzipDecoder::new(reader))),
+        "deflate" => Some(Box::new(ZlibDecoder::new(reader))),
+        "br" => Some(Box::new(BrotliDecoder::new(reader))),
+        "zstd" => Some(Box::new(ZstdDecoder::new(reader))),
+        _ => None,
+    }
+}
@@ -0,0 +1,104 @@
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use hyper::header::HeaderName;
+use hyper::http::Request;
+use std::path::{Path, PathBuf};
+
+use crate::available_encodings::{is_dictionary_encoding, AvailableEncodings};
+use crate::response_paths::add_extension;
+use crate::wildcard::wildcard_matches;
+
+// https://www.rfc-editor.org/rfc/rfc9842
+
+pub const AVAILABLE_DICTIONARY: HeaderName = HeaderName::from_static("available-dictionary");
+pub const USE_AS_DICTIONARY: HeaderName = HeaderName::from_static("use-as-dictionary");
+
+// dictionaries are identified by their sha-256 hash
+const HASH_LENGTH: usize = 32;
+
+// Available-Dictionary: :<base64 sha-256>:
+// returns the hash as lowercase hex
+pub fn get_dictionary_hash(req: &Request<()>) -> Option<String> {
+    let available_dictionary = match req.headers().get(AVAILABLE_DICTIONARY) {
+        Some(ad) => ad,
+        _ => return None,
+    };
+
+    let ad_str = match available_dictionary.to_str() {
+        Ok(s) => s.trim(),
+        _ => return None,
+    };
+
+    let encoded_hash = match ad_str.strip_prefix(":").and_then(|s| s.strip_suffix(":")) {
+        Some(eh) => eh,
+        _ => return None,
+    };
+
+    let hash = match STANDARD.decode(encoded_hash) {
+        Ok(h) => h,
+        _ => return None,
+    };
+
+    if HASH_LENGTH != hash.len() {
+        return None;
+    }
+
+    let mut hex = String::with_capacity(HASH_LENGTH * 2);
+    for byte in hash {
+        hex.push_str(&format!("{:02x}", byte));
+    }
+
+    Some(hex)
+}
+
+// ./www/app.js -> ./www/app.js.<hex hash>.dcb
+pub fn get_dictionary_paths(
+    filepath: &Path,
+    available_encodings: &AvailableEncodings,
+    hash: &str,
+    encoding: &str,
+) -> Vec<PathBuf> {
+    if !is_dictionary_encoding(encoding) {
+        return Vec::new();
+    }
+
+    available_encodings
+        .get_extensions(encoding)
+        .iter()
+        .map(|ext| add_extension(filepath, &(".".to_string() + hash + ext)))
+        .collect()
+}
+
+// Use-As-Dictionary: match="<pattern>"
+pub fn get_use_as_dictionary(
+    req: &Request<()>,
+    use_as_dictionary: &Option<Vec<String>>,
+) -> Option<String> {
+    let patterns = match use_as_dictionary {
+        Some(uad) => uad,
+        _ => return None,
+    };
+
+    let path = req.uri().path();
+    for pattern in patterns {
+        // match patterns only support "*" wildcards
+        if wildcard_matches(pattern.as_bytes(), path.as_bytes()) {
+            return Some(format!("match=\"{}\"", escape_sf_string(pattern)));
+        }
+    }
+
+    None
+}
+
+// https://www.rfc-editor.org/rfc/rfc8941#name-strings
+fn escape_sf_string(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if '"' == c || '\\' == c {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+
+    escaped
+}
@@ -1,44 +1,55 @@
 use futures_util::TryStreamExt;
 use http_body_util::{BodyExt, StreamBody};
-use hyper::body::{Frame, Incoming};
-use hyper::header::{CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_TYPE};
+use hyper::body::Frame;
+use hyper::header::{
+    ACCEPT_RANGES, CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_LOCATION, CONTENT_TYPE, ETAG,
+    LAST_MODIFIED,
+};
 use hyper::http::{Request, Response};
 use hyper::StatusCode;
 use std::path;
-use std::path::PathBuf;
+use std::path::Path;
 use tokio::fs;
 use tokio::fs::File;
 use tokio_util::io::ReaderStream;
 
+use crate::compression::has_encoder;
+use crate::compression_cache::{cache_is_fresh, get_cache_path, spawn_compression};
 use crate::content_type::get_content_type;
-use crate::last_resort_response::build_last_resort_response;
+use crate::decompression::get_decoder;
+use crate::dictionary::{get_dictionary_hash, get_dictionary_paths};
+use crate::last_resort_response::{build_last_resort_response, NOT_ACCEPTABLE_406, NOT_FOUND_404};
+use crate::preconditions::{
+    build_not_modified_response, format_http_date, get_etag, get_last_modified, is_not_modified,
+};
 use crate::range_response::build_range_response;
-use crate::response_paths::{add_extension, get_encodings, get_path_from_request_url};
+use crate::response_paths::{
+    get_content_location, get_encoded_paths, get_encoded_sibling, get_encodings,
+    get_path_from_request_url, identity_is_acceptable,
+};
+use crate::service_requirements::ServiceRequirements;
+use crate::stale_encodings::skip_stale_encoded_file;
 use crate::type_flyweight::BoxedResponse;
 
-pub const NOT_FOUND_404: &str = "404 not found";
-
 pub async fn build_get_response(
-    req: Request<Incoming>,
-    directory: PathBuf,
-    content_encodings: Option<Vec<String>>,
-    fallback_404: Option<PathBuf>,
+    req: &Request<()>,
+    requirements: &ServiceRequirements,
 ) -> Result<BoxedResponse, hyper::http::Error> {
     // check for range request
-    if let Some(res) = build_range_response(&req, &directory, &content_encodings).await {
+    if let Some(res) = build_range_response(req, requirements).await {
         return res;
     }
 
     // request file
-    let encodings = get_encodings(&req, &content_encodings);
+    let encodings = get_encodings(req, &requirements.available_encodings);
 
     // serve file
-    if let Some(res) = build_file_response(&req, &directory, &encodings).await {
+    if let Some(res) = build_file_response(req, requirements, &encodings).await {
         return res;
     };
 
     // serve 404
-    if let Some(res) = build_not_found_response(&directory, &fallback_404, &encodings).await {
+    if let Some(res) = build_not_found_response(req, requirements, &encodings).await {
         return res;
     };
 
@@ -46,24 +57,24 @@ pub async fn build_get_response(
 }
 
 async fn build_file_response(
-    req: &Request<Incoming>,
-    directory: &PathBuf,
+    req: &Request<()>,
+    requirements: &ServiceRequirements,
     encodings: &Option<Vec<String>>,
 ) -> Option<Result<BoxedResponse, hyper::http::Error>> {
-    let filepath = match get_path_from_request_url(req, directory).await {
+    let filepath = match get_path_from_request_url(req, requirements).await {
         Some(fp) => fp,
         _ => return None,
     };
 
-    build_response(&filepath, StatusCode::OK, &encodings).await
+    build_response(req, requirements, &filepath, StatusCode::OK, encodings).await
 }
 
 async fn build_not_found_response(
-    directory: &PathBuf,
-    fallback_404: &Option<PathBuf>,
+    req: &Request<()>,
+    requirements: &ServiceRequirements,
     encodings: &Option<Vec<String>>,
 ) -> Option<Result<BoxedResponse, hyper::http::Error>> {
-    let fallback = match fallback_404 {
+    let fallback = match &requirements.fallback_404 {
         Some(fb) => fb,
         _ => return None,
     };
@@ -74,33 +85,100 @@ async fn build_not_found_response(
         _ => return None,
     };
 
-    if !fallback_404_abs.starts_with(directory) {
+    if !fallback_404_abs.starts_with(&requirements.directory) {
         return None;
     }
 
-    build_response(&fallback, StatusCode::NOT_FOUND, &encodings).await
+    build_response(
+        req,
+        requirements,
+        fallback,
+        StatusCode::NOT_FOUND,
+        encodings,
+    )
+    .await
 }
 
 async fn build_response(
-    filepath: &PathBuf,
+    req: &Request<()>,
+    requirements: &ServiceRequirements,
+    filepath: &Path,
     status_code: StatusCode,
     encodings: &Option<Vec<String>>,
 ) -> Option<Result<BoxedResponse, hyper::http::Error>> {
-    let content_type = get_content_type(&filepath);
+    let content_type = get_content_type(filepath);
+
+    // dictionary compressed encodings
+    if let Some(res) = compose_dictionary_response(
+        req,
+        requirements,
+        filepath,
+        content_type,
+        status_code,
+        encodings,
+    )
+    .await
+    {
+        return Some(res);
+    };
 
     // encodings
-    if let Some(res) =
-        compose_encoded_response(&filepath, content_type, status_code, &encodings).await
+    if let Some(res) = compose_encoded_response(
+        req,
+        requirements,
+        filepath,
+        content_type,
+        status_code,
+        encodings,
+    )
+    .await
     {
         return Some(res);
     };
 
+    // encodings compressed on first request
+    if StatusCode::OK == status_code {
+        if let Some(res) =
+            compose_cached_response(req, requirements, filepath, content_type, encodings).await
+        {
+            return Some(res);
+        }
+    }
+
+    // fallbacks disregard accept-encoding, requested files are not acceptable
+    if StatusCode::OK == status_code && !identity_is_acceptable(req) {
+        return Some(build_last_resort_response(
+            StatusCode::NOT_ACCEPTABLE,
+            NOT_ACCEPTABLE_406,
+        ));
+    }
+
     // origin target
-    compose_get_response(&filepath, content_type, status_code, None).await
+    if let Some(res) =
+        compose_get_response(req, requirements, filepath, content_type, status_code, None).await
+    {
+        return Some(res);
+    }
+
+    // only encoded files exist
+    if requirements.decompress_fallback {
+        return compose_decompressed_response(
+            req,
+            requirements,
+            filepath,
+            content_type,
+            status_code,
+        )
+        .await;
+    }
+
+    None
 }
 
 async fn compose_encoded_response(
-    filepath: &PathBuf,
+    req: &Request<()>,
+    requirements: &ServiceRequirements,
+    filepath: &Path,
     content_type: &str,
     status_code: StatusCode,
     encodings: &Option<Vec<String>>,
@@ -111,20 +189,136 @@ async fn compose_encoded_response(
     };
 
     for enc in encds {
-        if let Some(encoded_path) = add_extension(filepath, &enc) {
-            if let Some(res) =
-                compose_get_response(&encoded_path, content_type, status_code, Some(enc)).await
+        for encoded_path in get_encoded_paths(filepath, &requirements.available_encodings, enc) {
+            if requirements.skip_stale_encodings
+                && skip_stale_encoded_file(filepath, &encoded_path).await
+            {
+                continue;
+            }
+
+            if let Some(res) = compose_get_response(
+                req,
+                requirements,
+                &encoded_path,
+                content_type,
+                status_code,
+                Some(enc),
+            )
+            .await
             {
                 return Some(res);
             }
+        }
+    }
+
+    None
+}
+
+// files compressed with the client's dictionary are named after its hash
+async fn compose_dictionary_response(
+    req: &Request<()>,
+    requirements: &ServiceRequirements,
+    filepath: &Path,
+    content_type: &str,
+    status_code: StatusCode,
+    encodings: &Option<Vec<String>>,
+) -> Option<Result<BoxedResponse, hyper::http::Error>> {
+    let encds = match encodings {
+        Some(encds) => encds,
+        _ => return None,
+    };
+
+    let hash = get_dictionary_hash(req)?;
+
+    for enc in encds {
+        for dictionary_path in
+            get_dictionary_paths(filepath, &requirements.available_encodings, &hash, enc)
+        {
+            if requirements.skip_stale_encodings
+                && skip_stale_encoded_file(filepath, &dictionary_path).await
+            {
+                continue;
+            }
+
+            if let Some(res) = compose_get_response(
+                req,
+                requirements,
+                &dictionary_path,
+                content_type,
+                status_code,
+                Some(enc),
+            )
+            .await
+            {
+                return Some(res);
+            }
+        }
+    }
+
+    None
+}
+
+// A miss compresses the file in the background for the most preferred
+// encoding while this request is served without it.
+async fn compose_cached_response(
+    req: &Request<()>,
+    requirements: &ServiceRequirements,
+    filepath: &Path,
+    content_type: &str,
+    encodings: &Option<Vec<String>>,
+) -> Option<Result<BoxedResponse, hyper::http::Error>> {
+    let cache = match &requirements.compression_cache {
+        Some(c) => c,
+        _ => return None,
+    };
+
+    let encds = match encodings {
+        Some(encds) => encds,
+        _ => return None,
+    };
+
+    let mut preferred_encoding = None;
+    for enc in encds {
+        if !has_encoder(enc) {
+            continue;
+        }
+
+        let cache_path = match get_cache_path(requirements, cache, filepath, enc) {
+            Some(cp) => cp,
+            _ => continue,
         };
+
+        if cache_is_fresh(filepath, &cache_path).await {
+            if let Some(res) = compose_get_response(
+                req,
+                requirements,
+                &cache_path,
+                content_type,
+                StatusCode::OK,
+                Some(enc),
+            )
+            .await
+            {
+                return Some(res);
+            }
+        }
+
+        if preferred_encoding.is_none() {
+            preferred_encoding = Some(enc);
+        }
+    }
+
+    if let Some(enc) = preferred_encoding {
+        spawn_compression(requirements, cache, filepath, enc);
     }
 
     None
 }
 
-async fn compose_get_response(
-    filepath: &PathBuf,
+pub async fn compose_get_response(
+    req: &Request<()>,
+    requirements: &ServiceRequirements,
+    filepath: &Path,
     content_type: &str,
     status_code: StatusCode,
     content_encoding: Option<&str>,
@@ -138,6 +332,24 @@ async fn compose_get_response(
         return None;
     }
 
+    // validators and locations only describe successful responses
+    let (etag, last_modified, content_location) = match status_code {
+        StatusCode::OK => (
+            get_etag(&metadata, content_encoding),
+            get_last_modified(&metadata),
+            get_content_location(requirements, filepath),
+        ),
+        _ => (None, None, None),
+    };
+
+    if StatusCode::OK == status_code && is_not_modified(req, &etag, &last_modified) {
+        return Some(build_not_modified_response(
+            &etag,
+            &last_modified,
+            &content_location,
+        ));
+    }
+
     let file = match File::open(filepath).await {
         Ok(m) => m,
         _ => return None,
@@ -148,10 +360,27 @@ async fn compose_get_response(
         .header(CONTENT_TYPE, content_type)
         .header(CONTENT_LENGTH, metadata.len());
 
+    // only requested files are served as ranges, never fallbacks
+    if StatusCode::OK == status_code {
+        builder = builder.header(ACCEPT_RANGES, "bytes");
+    }
+
     if let Some(enc) = content_encoding {
         builder = builder.header(CONTENT_ENCODING, enc);
     }
 
+    if let Some(et) = etag {
+        builder = builder.header(ETAG, et);
+    }
+
+    if let Some(lm) = last_modified {
+        builder = builder.header(LAST_MODIFIED, format_http_date(&lm));
+    }
+
+    if let Some(cl) = content_location {
+        builder = builder.header(CONTENT_LOCATION, cl);
+    }
+
     // https://github.com/hyperium/hyper/blob/master/examples/send_file.rs
     let reader_stream = ReaderStream::new(file);
     let stream_body = StreamBody::new(reader_stream.map_ok(Frame::data));
@@ -159,3 +388,60 @@ async fn compose_get_response(
 
     Some(builder.body(boxed_body))
 }
+
+// Streams an encoded sibling decoded on the fly. The decoded length is
+// unknown so no Content-Length is sent and ranges are not supported.
+async fn compose_decompressed_response(
+    req: &Request<()>,
+    requirements: &ServiceRequirements,
+    filepath: &Path,
+    content_type: &str,
+    status_code: StatusCode,
+) -> Option<Result<BoxedResponse, hyper::http::Error>> {
+    let (encoded_path, encoding) =
+        get_encoded_sibling(filepath, &requirements.available_encodings).await?;
+
+    let metadata = match fs::metadata(&encoded_path).await {
+        Ok(m) => m,
+        _ => return None,
+    };
+
+    // decoded bytes are derived from the encoded file so the validator is weak
+    let (etag, last_modified) = match status_code {
+        StatusCode::OK => (
+            get_etag(&metadata, Some(&(encoding.clone() + "-decoded")))
+                .map(|et| "W/".to_string() + &et),
+            get_last_modified(&metadata),
+        ),
+        _ => (None, None),
+    };
+
+    if StatusCode::OK == status_code && is_not_modified(req, &etag, &last_modified) {
+        return Some(build_not_modified_response(&etag, &last_modified, &None));
+    }
+
+    let file = match File::open(&encoded_path).await {
+        Ok(m) => m,
+        _ => return None,
+    };
+
+    let decoder = get_decoder(&encoding, file)?;
+
+    let mut builder = Response::builder()
+        .status(status_code)
+        .header(CONTENT_TYPE, content_type);
+
+    if let Some(et) = etag {
+        builder = builder.header(ETAG, et);
+    }
+
+    if let Some(lm) = last_modified {
+        builder = builder.header(LAST_MODIFIED, format_http_date(&lm));
+    }
+
+    let reader_stream = ReaderStream::new(decoder);
+    let stream_body = StreamBody::new(reader_stream.map_ok(Frame::data));
+    let boxed_body = stream_body.boxed();
+
+    Some(builder.body(boxed_body))
+}
@@ -1,13 +1,15 @@
-use bytes;
 use http_body_util::{BodyExt, Full};
-use hyper::header::{HeaderValue, CONTENT_TYPE};
+use hyper::header::{HeaderValue, CONTENT_LENGTH, CONTENT_RANGE, CONTENT_TYPE};
 use hyper::http::Response;
 use hyper::StatusCode;
 
 use crate::content_type::HTML;
 use crate::type_flyweight::BoxedResponse;
 
+pub const FORBIDDEN_403: &str = "403 forbidden";
 pub const NOT_FOUND_404: &str = "404 not found";
+pub const NOT_ACCEPTABLE_406: &str = "406 not acceptable";
+pub const RANGE_NOT_SATISFIABLE_416: &str = "416 range not satisfiable";
 
 pub fn build_last_resort_response(
     status_code: StatusCode,
@@ -16,9 +18,26 @@ pub fn build_last_resort_response(
     Response::builder()
         .status(status_code)
         .header(CONTENT_TYPE, HeaderValue::from_static(HTML))
+        .header(CONTENT_LENGTH, body.len())
         .body(
             Full::new(bytes::Bytes::from(body))
                 .map_err(|e| match e {})
                 .boxed(),
         )
 }
+
+// https://www.rfc-editor.org/rfc/rfc9110#name-416-range-not-satisfiable
+pub fn build_range_not_satisfiable_response(
+    size: u64,
+) -> Result<BoxedResponse, hyper::http::Error> {
+    Response::builder()
+        .status(StatusCode::RANGE_NOT_SATISFIABLE)
+        .header(CONTENT_TYPE, HeaderValue::from_static(HTML))
+        .header(CONTENT_RANGE, format!("bytes */{}", size))
+        .header(CONTENT_LENGTH, RANGE_NOT_SATISFIABLE_416.len())
+        .body(
+            Full::new(bytes::Bytes::from(RANGE_NOT_SATISFIABLE_416))
+                .map_err(|e| match e {})
+                .boxed(),
+        )
+}
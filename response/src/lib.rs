@@ -1,12 +1,30 @@
+mod accept_encoding;
 mod available_encodings;
+mod client_authorization;
+mod compression;
+mod compression_cache;
 mod content_type;
+mod decompression;
+mod dictionary;
 mod get_response;
-mod head_response;
 mod last_resort_response;
+mod multipart_response;
+mod preconditions;
 mod range_response;
 mod response_paths;
 mod responses;
+mod service_requirements;
+mod stale_encodings;
 mod type_flyweight;
+mod wildcard;
 
+pub use crate::available_encodings::AvailableEncodings;
+pub use crate::client_authorization::{ClientNames, ClientRule};
+pub use crate::compression::{encode_file, has_encoder, Quality};
+pub use crate::compression_cache::CompressionCache;
+pub use crate::range_response::RangeLimits;
+pub use crate::response_paths::{add_extension, get_normalized_path};
 pub use crate::responses::build_response;
+pub use crate::service_requirements::ServiceRequirements;
+pub use crate::stale_encodings::stale_encodings_skipped;
 pub use crate::type_flyweight::BoxedResponse;
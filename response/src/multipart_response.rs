@@ -0,0 +1,187 @@
+use bytes::Bytes;
+use futures_util::{stream, StreamExt, TryStreamExt};
+use http_body_util::{BodyExt, StreamBody};
+use hyper::body::Frame;
+use hyper::header::{
+    CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_LOCATION, CONTENT_TYPE, ETAG, LAST_MODIFIED,
+};
+use hyper::http::{Response, StatusCode};
+use std::collections::hash_map::RandomState;
+use std::fs::Metadata;
+use std::hash::{BuildHasher, Hasher};
+use std::io::SeekFrom;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::fs::File;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use tokio_util::io::ReaderStream;
+
+use crate::preconditions::{format_http_date, get_etag, get_last_modified};
+use crate::range_response::{build_content_range_header_str, STREAM_CAPACITY};
+use crate::type_flyweight::BoxedResponse;
+
+// https://www.rfc-editor.org/rfc/rfc9110#name-media-type-multipart-byteran
+
+// Every part is streamed from disk on demand. Only part headers are
+// allocated, so memory use does not grow with the size of the ranges.
+
+// ranges must be inclusive, sorted, and coalesced
+pub fn compose_multipart_response(
+    filepath: &Path,
+    metadata: &Metadata,
+    content_type: &str,
+    content_encoding: Option<&str>,
+    content_location: Option<String>,
+    ranges: Vec<(u64, u64)>,
+) -> Result<BoxedResponse, hyper::http::Error> {
+    let boundary = get_boundary();
+    let size = metadata.len();
+
+    let mut content_length: u64 = 0;
+    let mut parts = Vec::with_capacity(ranges.len());
+    for (index, (start, end)) in ranges.into_iter().enumerate() {
+        let part_header = build_part_header_str(index, &boundary, content_type, start, end, size);
+        content_length += part_header.len() as u64 + (end - start + 1);
+        parts.push((Bytes::from(part_header), start, end));
+    }
+
+    let closing = format!("\r\n--{}--\r\n", boundary);
+    content_length += closing.len() as u64;
+
+    let filepath = filepath.to_path_buf();
+    let part_streams = stream::iter(parts).flat_map(move |(part_header, start, end)| {
+        let header_stream = stream::once(async move { Ok(part_header) });
+        let body_stream =
+            stream::once(get_range_stream(filepath.clone(), start, end)).try_flatten();
+
+        header_stream.chain(body_stream)
+    });
+    let closing_stream = stream::once(async move { Ok(Bytes::from(closing)) });
+
+    let stream_body = StreamBody::new(part_streams.chain(closing_stream).map_ok(Frame::data));
+    let boxed_body = BodyExt::boxed(stream_body);
+
+    let mut builder = Response::builder()
+        .status(StatusCode::PARTIAL_CONTENT)
+        .header(
+            CONTENT_TYPE,
+            format!("multipart/byteranges; boundary={}", boundary),
+        )
+        .header(CONTENT_LENGTH, content_length);
+
+    if let Some(enc) = content_encoding {
+        builder = builder.header(CONTENT_ENCODING, enc);
+    }
+
+    if let Some(et) = get_etag(metadata, content_encoding) {
+        builder = builder.header(ETAG, et);
+    }
+
+    if let Some(lm) = get_last_modified(metadata) {
+        builder = builder.header(LAST_MODIFIED, format_http_date(&lm));
+    }
+
+    if let Some(cl) = content_location {
+        builder = builder.header(CONTENT_LOCATION, cl);
+    }
+
+    builder.body(boxed_body)
+}
+
+// sort and merge overlapping or adjacent inclusive ranges
+pub fn coalesce_ranges(mut ranges: Vec<(u64, u64)>) -> Vec<(u64, u64)> {
+    ranges.sort();
+
+    let mut coalesced: Vec<(u64, u64)> = Vec::with_capacity(ranges.len());
+    for (start, end) in ranges {
+        if let Some(last) = coalesced.last_mut() {
+            if start <= last.1.saturating_add(1) {
+                last.1 = last.1.max(end);
+                continue;
+            }
+        }
+
+        coalesced.push((start, end));
+    }
+
+    coalesced
+}
+
+async fn get_range_stream(
+    filepath: PathBuf,
+    start: u64,
+    end: u64,
+) -> Result<ReaderStream<tokio::io::Take<File>>, std::io::Error> {
+    let mut file = File::open(filepath).await?;
+    file.seek(SeekFrom::Start(start)).await?;
+
+    Ok(ReaderStream::with_capacity(
+        file.take(end - start + 1),
+        STREAM_CAPACITY,
+    ))
+}
+
+fn build_part_header_str(
+    index: usize,
+    boundary: &str,
+    content_type: &str,
+    start: u64,
+    end: u64,
+    size: u64,
+) -> String {
+    // every delimiter after the first is preceded by a CRLF
+    let delimiter_prefix = match index {
+        0 => "",
+        _ => "\r\n",
+    };
+
+    format!(
+        "{}--{}\r\nContent-Type: {}\r\nContent-Range: {}\r\n\r\n",
+        delimiter_prefix,
+        boundary,
+        content_type,
+        build_content_range_header_str(start, end, size)
+    )
+}
+
+fn get_boundary() -> String {
+    let mut hasher = RandomState::new().build_hasher();
+    if let Ok(since_epoch) = SystemTime::now().duration_since(UNIX_EPOCH) {
+        hasher.write_u128(since_epoch.as_nanos());
+    }
+
+    format!(
+        "{:016x}{:016x}",
+        hasher.finish(),
+        RandomState::new().build_hasher().finish()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn coalesces_overlapping_and_adjacent_ranges() {
+        assert_eq!(coalesce_ranges(vec![(5, 9), (0, 2), (3, 4)]), vec![(0, 9)]);
+        assert_eq!(coalesce_ranges(vec![(0, 5), (2, 3)]), vec![(0, 5)]);
+        assert_eq!(coalesce_ranges(vec![(0, 5), (4, 8)]), vec![(0, 8)]);
+    }
+
+    #[test]
+    fn keeps_separate_ranges_sorted() {
+        assert_eq!(
+            coalesce_ranges(vec![(6, 7), (0, 1), (3, 4)]),
+            vec![(0, 1), (3, 4), (6, 7)]
+        );
+        assert_eq!(coalesce_ranges(Vec::new()), Vec::new());
+    }
+
+    #[test]
+    fn coalesces_ranges_ending_at_the_maximum() {
+        assert_eq!(
+            coalesce_ranges(vec![(0, u64::MAX), (u64::MAX, u64::MAX)]),
+            vec![(0, u64::MAX)]
+        );
+    }
+}
@@ -0,0 +1,303 @@
+use http_body_util::{BodyExt, Empty};
+use hyper::header::{
+    CONTENT_LOCATION, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, IF_RANGE, LAST_MODIFIED,
+};
+use hyper::http::{Request, Response};
+use hyper::StatusCode;
+use std::fs::Metadata;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::type_flyweight::BoxedResponse;
+
+// https://www.rfc-editor.org/rfc/rfc9110#name-etag
+// https://www.rfc-editor.org/rfc/rfc9110#name-evaluation
+
+// ETags are derived from mtime, size, and content-encoding so
+// precompressed variants never share a validator with the identity file.
+pub fn get_etag(metadata: &Metadata, content_encoding: Option<&str>) -> Option<String> {
+    let modified = match metadata.modified() {
+        Ok(m) => m,
+        _ => return None,
+    };
+
+    let since_epoch = match modified.duration_since(UNIX_EPOCH) {
+        Ok(d) => d,
+        _ => return None,
+    };
+
+    let mut etag = format!(
+        "\"{:x}.{:x}-{:x}",
+        since_epoch.as_secs(),
+        since_epoch.subsec_nanos(),
+        metadata.len()
+    );
+
+    if let Some(enc) = content_encoding {
+        etag.push('-');
+        etag.push_str(enc);
+    }
+    etag.push('"');
+
+    Some(etag)
+}
+
+pub fn get_last_modified(metadata: &Metadata) -> Option<SystemTime> {
+    metadata.modified().ok()
+}
+
+pub fn is_not_modified(
+    req: &Request<()>,
+    etag: &Option<String>,
+    last_modified: &Option<SystemTime>,
+) -> bool {
+    // If-None-Match takes precedence over If-Modified-Since
+    if let Some(if_none_match) = req.headers().get(IF_NONE_MATCH) {
+        let etag = match etag {
+            Some(e) => e,
+            _ => return false,
+        };
+
+        return match if_none_match.to_str() {
+            Ok(s) => etag_list_matches_weak(s, etag),
+            _ => false,
+        };
+    }
+
+    let if_modified_since = match req.headers().get(IF_MODIFIED_SINCE) {
+        Some(ims) => ims,
+        _ => return false,
+    };
+
+    let last_modified = match last_modified {
+        Some(lm) => lm,
+        _ => return false,
+    };
+
+    let since = match if_modified_since.to_str() {
+        Ok(s) => match httpdate::parse_http_date(s) {
+            Ok(st) => st,
+            _ => return false,
+        },
+        _ => return false,
+    };
+
+    // http-dates have a resolution of one second
+    truncate_to_seconds(last_modified) <= since
+}
+
+// https://www.rfc-editor.org/rfc/rfc9110#name-if-range
+pub fn if_range_is_satisfied(
+    req: &Request<()>,
+    etag: &Option<String>,
+    last_modified: &Option<SystemTime>,
+) -> bool {
+    let if_range = match req.headers().get(IF_RANGE) {
+        Some(ir) => ir,
+        _ => return true,
+    };
+
+    let if_range_str = match if_range.to_str() {
+        Ok(s) => s.trim(),
+        _ => return false,
+    };
+
+    // entity-tags require a strong comparison
+    if if_range_str.starts_with("W/") {
+        return false;
+    }
+
+    if if_range_str.starts_with('"') {
+        return match etag {
+            Some(et) => !et.starts_with("W/") && et == if_range_str,
+            _ => false,
+        };
+    }
+
+    let last_modified = match last_modified {
+        Some(lm) => lm,
+        _ => return false,
+    };
+
+    match httpdate::parse_http_date(if_range_str) {
+        Ok(date) => truncate_to_seconds(last_modified) == date,
+        _ => false,
+    }
+}
+
+pub fn format_http_date(time: &SystemTime) -> String {
+    httpdate::fmt_http_date(*time)
+}
+
+pub fn build_not_modified_response(
+    etag: &Option<String>,
+    last_modified: &Option<SystemTime>,
+    content_location: &Option<String>,
+) -> Result<BoxedResponse, hyper::http::Error> {
+    let mut builder = Response::builder().status(StatusCode::NOT_MODIFIED);
+
+    if let Some(et) = etag {
+        builder = builder.header(ETAG, et);
+    }
+
+    if let Some(lm) = last_modified {
+        builder = builder.header(LAST_MODIFIED, format_http_date(lm));
+    }
+
+    if let Some(cl) = content_location {
+        builder = builder.header(CONTENT_LOCATION, cl);
+    }
+
+    builder.body(Empty::new().map_err(|e| match e {}).boxed())
+}
+
+pub fn etag_list_matches_weak(etag_list: &str, etag: &str) -> bool {
+    let trimmed = etag_list.trim();
+    if "*" == trimmed {
+        return true;
+    }
+
+    let opaque_tag = strip_weak_prefix(etag);
+    for candidate in get_etags(trimmed) {
+        if strip_weak_prefix(candidate) == opaque_tag {
+            return true;
+        }
+    }
+
+    false
+}
+
+fn strip_weak_prefix(etag: &str) -> &str {
+    match etag.strip_prefix("W/") {
+        Some(e) => e,
+        _ => etag,
+    }
+}
+
+// entity-tags are quoted strings and may contain commas
+fn get_etags(etag_list: &str) -> Vec<&str> {
+    let mut etags = Vec::new();
+
+    let mut remaining = etag_list;
+    loop {
+        remaining = remaining.trim_start_matches([' ', '\t', ',']);
+        if remaining.is_empty() {
+            break;
+        }
+
+        let offset = match remaining.starts_with("W/") {
+            true => 2,
+            _ => 0,
+        };
+
+        if !remaining[offset..].starts_with('"') {
+            break;
+        }
+
+        let closing_quote = match remaining[offset + 1..].find('"') {
+            Some(index) => offset + 1 + index,
+            _ => break,
+        };
+
+        etags.push(&remaining[..closing_quote + 1]);
+        remaining = &remaining[closing_quote + 1..];
+    }
+
+    etags
+}
+
+fn truncate_to_seconds(time: &SystemTime) -> SystemTime {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(d) => UNIX_EPOCH + std::time::Duration::from_secs(d.as_secs()),
+        _ => *time,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    const ETAG_STR: &str = "\"65f1a2b3.0-a\"";
+
+    fn get_if_range_request(if_range: &str) -> Request<()> {
+        Request::builder()
+            .header(IF_RANGE, if_range)
+            .body(())
+            .unwrap()
+    }
+
+    fn get_validators() -> (Option<String>, Option<SystemTime>) {
+        let last_modified = UNIX_EPOCH + Duration::from_millis(1_700_000_000_250);
+        (Some(ETAG_STR.to_string()), Some(last_modified))
+    }
+
+    #[test]
+    fn if_range_matches_a_strong_etag() {
+        let (etag, last_modified) = get_validators();
+
+        let req = get_if_range_request(ETAG_STR);
+        assert!(if_range_is_satisfied(&req, &etag, &last_modified));
+
+        let req = get_if_range_request("\"65f1a2b3.0-b\"");
+        assert!(!if_range_is_satisfied(&req, &etag, &last_modified));
+    }
+
+    #[test]
+    fn if_range_never_matches_a_weak_etag() {
+        let (etag, last_modified) = get_validators();
+
+        let req = get_if_range_request(&format!("W/{}", ETAG_STR));
+        assert!(!if_range_is_satisfied(&req, &etag, &last_modified));
+    }
+
+    #[test]
+    fn if_range_compares_dates_to_the_second() {
+        let (etag, last_modified) = get_validators();
+
+        // sub-second precision of the file is ignored
+        let req = get_if_range_request("Tue, 14 Nov 2023 22:13:20 GMT");
+        assert!(if_range_is_satisfied(&req, &etag, &last_modified));
+
+        let req = get_if_range_request("Tue, 14 Nov 2023 22:13:21 GMT");
+        assert!(!if_range_is_satisfied(&req, &etag, &last_modified));
+
+        let req = get_if_range_request("Tue, 14 Nov 2023 22:13:19 GMT");
+        assert!(!if_range_is_satisfied(&req, &etag, &last_modified));
+    }
+
+    #[test]
+    fn if_range_rejects_malformed_values() {
+        let (etag, last_modified) = get_validators();
+
+        for if_range in ["yesterday", "65f1a2b3.0-a", "", "\"65f1a2b3.0-a"] {
+            let req = get_if_range_request(if_range);
+            assert!(!if_range_is_satisfied(&req, &etag, &last_modified));
+        }
+    }
+
+    #[test]
+    fn if_range_is_satisfied_without_the_header() {
+        let (etag, last_modified) = get_validators();
+
+        let req = Request::builder().body(()).unwrap();
+        assert!(if_range_is_satisfied(&req, &etag, &last_modified));
+    }
+
+    #[test]
+    fn splits_etag_lists() {
+        assert_eq!(
+            get_etags("\"a\", W/\"b\",\"c,d\""),
+            vec!["\"a\"", "W/\"b\"", "\"c,d\""]
+        );
+        assert_eq!(get_etags("\"a\", b, \"c\""), vec!["\"a\""]);
+        assert_eq!(get_etags("\"a"), Vec::<&str>::new());
+        assert_eq!(get_etags(""), Vec::<&str>::new());
+    }
+
+    #[test]
+    fn if_none_match_compares_weakly() {
+        assert!(etag_list_matches_weak("W/\"a\", \"b\"", "\"a\""));
+        assert!(etag_list_matches_weak("*", "\"a\""));
+        assert!(!etag_list_matches_weak("\"b\"", "\"a\""));
+    }
+}
@@ -1,78 +1,110 @@
 use futures_util::TryStreamExt;
 use http_body_util::{BodyExt, StreamBody};
 use hyper::body::Frame;
-use hyper::body::Incoming as IncomingBody;
-use hyper::header::{CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_RANGE, CONTENT_TYPE, RANGE};
+use hyper::header::{
+    CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_LOCATION, CONTENT_RANGE, CONTENT_TYPE, ETAG,
+    LAST_MODIFIED, RANGE,
+};
 use hyper::http::{Request, Response, StatusCode};
+use serde::{Deserialize, Serialize};
+use std::fs::Metadata;
 use std::io::SeekFrom;
-use std::path::PathBuf;
+use std::path::Path;
 use tokio::fs;
 use tokio::fs::File;
-use tokio::io::AsyncSeekExt;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
 use tokio_util::io::ReaderStream;
 
 use crate::content_type::get_content_type;
-use crate::last_resort_response::{build_last_resort_response, NOT_FOUND_404};
-use crate::response_paths::{add_extension, get_encodings, get_path_from_request_url};
+use crate::get_response::compose_get_response;
+use crate::last_resort_response::{
+    build_last_resort_response, build_range_not_satisfiable_response, NOT_ACCEPTABLE_406,
+};
+use crate::multipart_response::{coalesce_ranges, compose_multipart_response};
+use crate::preconditions::{
+    build_not_modified_response, format_http_date, get_etag, get_last_modified,
+    if_range_is_satisfied, is_not_modified,
+};
+use crate::response_paths::{
+    get_content_location, get_encoded_paths, get_encodings, get_path_from_request_url,
+    identity_is_acceptable,
+};
+use crate::service_requirements::ServiceRequirements;
+use crate::stale_encodings::skip_stale_encoded_file;
 use crate::type_flyweight::BoxedResponse;
 
 // Range: <unit>=<range-start>-
 // Range: <unit>=<range-start>-<range-end>
 // Range: <unit>=-<suffix-length>
 
-// multi range requests require an entirely different strategy
+// multi range requests are served as multipart/byteranges
 // Range: <unit>=<range-start>-<range-end>, …, <range-startN>-<range-endN>
 
-pub const RANGE_NOT_SATISFIABLE_416: &str = "416 range not satisfiable";
+// https://www.rfc-editor.org/rfc/rfc9110#name-byte-ranges
+// range ends are inclusive: bytes=0-0 is the first byte
 
+// bounded read size per frame so memory stays flat for large files
+pub const STREAM_CAPACITY: usize = 64 * 1024;
+
+// Requests exceeding these limits are rejected with a 416.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RangeLimits {
+    pub max_ranges: usize,
+    pub max_bytes: u64,
+}
+
+impl Default for RangeLimits {
+    fn default() -> RangeLimits {
+        RangeLimits {
+            max_ranges: 16,
+            max_bytes: 256 * 1024 * 1024,
+        }
+    }
+}
+
+// Missing files and invalid ranges return nothing so the request continues
+// as a regular GET with decompression and filepath_404.
 pub async fn build_range_response(
-    req: &Request<IncomingBody>,
-    directory: &PathBuf,
-    content_encodings: &Option<Vec<String>>,
+    req: &Request<()>,
+    requirements: &ServiceRequirements,
 ) -> Option<Result<BoxedResponse, hyper::http::Error>> {
     let range_header = match get_range_header(req) {
         Some(rh) => rh,
         _ => return None,
     };
 
-    let ranges = get_ranges(&range_header);
-    if let Some(res) = compose_range_response(req, directory, content_encodings, ranges).await {
-        return Some(res);
-    };
+    let ranges = get_ranges(&range_header)?;
 
-    Some(build_last_resort_response(
-        StatusCode::NOT_FOUND,
-        NOT_FOUND_404,
-    ))
+    compose_range_response(req, requirements, ranges).await
 }
 
-fn get_range_header(req: &Request<IncomingBody>) -> Option<String> {
-    let accept_encoding_header = match req.headers().get(RANGE) {
-        Some(enc) => enc,
+fn get_range_header(req: &Request<()>) -> Option<String> {
+    let range_header = match req.headers().get(RANGE) {
+        Some(rng) => rng,
         _ => return None,
     };
 
-    match accept_encoding_header.to_str() {
+    match range_header.to_str() {
         Ok(s) => Some(s.to_string()),
         _ => None,
     }
 }
 
 // on any fail return nothing
-fn get_ranges(range_str: &str) -> Option<Vec<(Option<usize>, Option<usize>)>> {
+fn get_ranges(range_str: &str) -> Option<Vec<(Option<u64>, Option<u64>)>> {
     let stripped_range = range_str.trim();
     let range_values_str = match stripped_range.strip_prefix("bytes=") {
         Some(r) => r,
         _ => return None,
     };
 
-    let mut ranges: Vec<(Option<usize>, Option<usize>)> = Vec::new();
+    let mut ranges: Vec<(Option<u64>, Option<u64>)> = Vec::new();
     for value_str in range_values_str.split(",") {
         let trimmed_value_str = value_str.trim();
 
         // prefix range
         if let Some(without_suffix) = trimmed_value_str.strip_suffix("-") {
-            let start_range_int: usize = match without_suffix.parse() {
+            let start_range_int: u64 = match without_suffix.parse() {
                 Ok(sri) => sri,
                 _ => return None,
             };
@@ -83,7 +115,7 @@ fn get_ranges(range_str: &str) -> Option<Vec<(Option<usize>, Option<usize>)>> {
 
         // suffix-range
         if let Some(without_prefix) = trimmed_value_str.strip_prefix("-") {
-            let end_range_int: usize = match without_prefix.parse() {
+            let end_range_int: u64 = match without_prefix.parse() {
                 Ok(sri) => sri,
                 _ => return None,
             };
@@ -101,10 +133,10 @@ fn get_ranges(range_str: &str) -> Option<Vec<(Option<usize>, Option<usize>)>> {
         ranges.push(start_end_range)
     }
 
-    return Some(ranges);
+    Some(ranges)
 }
 
-fn get_window_range(range_chunk: &str) -> Option<(Option<usize>, Option<usize>)> {
+fn get_window_range(range_chunk: &str) -> Option<(Option<u64>, Option<u64>)> {
     let mut values = range_chunk.split("-");
 
     let start_range_str = match values.next() {
@@ -117,17 +149,21 @@ fn get_window_range(range_chunk: &str) -> Option<(Option<usize>, Option<usize>)>
         _ => return None,
     };
 
-    let start_range_int: usize = match start_range_str.parse() {
+    if values.next().is_some() {
+        return None;
+    }
+
+    let start_range_int: u64 = match start_range_str.parse() {
         Ok(sri) => sri,
         _ => return None,
     };
 
-    let end_range_int: usize = match end_range_str.parse() {
+    let end_range_int: u64 = match end_range_str.parse() {
         Ok(sri) => sri,
         _ => return None,
     };
 
-    if start_range_int < end_range_int {
+    if start_range_int <= end_range_int {
         return Some((Some(start_range_int), Some(end_range_int)));
     }
 
@@ -135,62 +171,64 @@ fn get_window_range(range_chunk: &str) -> Option<(Option<usize>, Option<usize>)>
 }
 
 async fn compose_range_response(
-    req: &Request<IncomingBody>,
-    directory: &PathBuf,
-    content_encodings: &Option<Vec<String>>,
-    ranges: Option<Vec<(Option<usize>, Option<usize>)>>,
+    req: &Request<()>,
+    requirements: &ServiceRequirements,
+    ranges: Vec<(Option<u64>, Option<u64>)>,
 ) -> Option<Result<BoxedResponse, hyper::http::Error>> {
-    let rngs = match ranges {
-        Some(r) => r,
-        _ => {
-            return Some(build_last_resort_response(
-                StatusCode::RANGE_NOT_SATISFIABLE,
-                RANGE_NOT_SATISFIABLE_416,
-            ))
-        }
-    };
-
-    let filepath = match get_path_from_request_url(req, directory).await {
+    let filepath = match get_path_from_request_url(req, requirements).await {
         Some(fp) => fp,
         _ => return None,
     };
 
-    let encodings = get_encodings(req, content_encodings);
-
-    if 1 == rngs.len() {
-        if let Some(res) = build_single_range_response(&filepath, encodings, rngs).await {
-            return Some(res);
-        }
-    }
+    let encodings = get_encodings(req, &requirements.available_encodings);
 
-    None
+    build_ranges_response(req, requirements, &filepath, encodings, &ranges).await
 }
 
-fn build_content_range_header_str(start: &usize, end: &usize, size: &usize) -> String {
-    "bytes ".to_string() + &start.to_string() + "-" + &end.to_string() + "/" + &size.to_string()
+pub fn build_content_range_header_str(start: u64, end: u64, size: u64) -> String {
+    format!("bytes {}-{}/{}", start, end, size)
 }
 
-async fn build_single_range_response(
-    filepath: &PathBuf,
+async fn build_ranges_response(
+    req: &Request<()>,
+    requirements: &ServiceRequirements,
+    filepath: &Path,
     encodings: Option<Vec<String>>,
-    ranges: Vec<(Option<usize>, Option<usize>)>,
+    ranges: &[(Option<u64>, Option<u64>)],
 ) -> Option<Result<BoxedResponse, hyper::http::Error>> {
-    let content_type = get_content_type(&filepath);
-
-    if let Some(res) = compose_encoded_response(&filepath, content_type, &encodings, &ranges).await
+    let content_type = get_content_type(filepath);
+
+    if let Some(res) = compose_encoded_response(
+        req,
+        requirements,
+        filepath,
+        content_type,
+        &encodings,
+        ranges,
+    )
+    .await
     {
         return Some(res);
     };
 
+    if !identity_is_acceptable(req) {
+        return Some(build_last_resort_response(
+            StatusCode::NOT_ACCEPTABLE,
+            NOT_ACCEPTABLE_406,
+        ));
+    }
+
     // origin target
-    compose_single_range_response(&filepath, content_type, None, &ranges).await
+    compose_ranges_response(req, requirements, filepath, content_type, None, ranges).await
 }
 
 async fn compose_encoded_response(
-    filepath: &PathBuf,
+    req: &Request<()>,
+    requirements: &ServiceRequirements,
+    filepath: &Path,
     content_type: &str,
     encodings: &Option<Vec<String>>,
-    ranges: &Vec<(Option<usize>, Option<usize>)>,
+    ranges: &[(Option<u64>, Option<u64>)],
 ) -> Option<Result<BoxedResponse, hyper::http::Error>> {
     let encds = match encodings {
         Some(encds) => encds,
@@ -198,53 +236,146 @@ async fn compose_encoded_response(
     };
 
     for enc in encds {
-        if let Some(encoded_path) = add_extension(filepath, &enc) {
-            if let Some(res) =
-                compose_single_range_response(&encoded_path, content_type, Some(enc), ranges).await
+        for encoded_path in get_encoded_paths(filepath, &requirements.available_encodings, enc) {
+            if requirements.skip_stale_encodings
+                && skip_stale_encoded_file(filepath, &encoded_path).await
+            {
+                continue;
+            }
+
+            if let Some(res) = compose_ranges_response(
+                req,
+                requirements,
+                &encoded_path,
+                content_type,
+                Some(enc),
+                ranges,
+            )
+            .await
             {
                 return Some(res);
             }
-        };
+        }
     }
 
     None
 }
 
-async fn compose_single_range_response(
-    filepath: &PathBuf,
+async fn compose_ranges_response(
+    req: &Request<()>,
+    requirements: &ServiceRequirements,
+    filepath: &Path,
     content_type: &str,
     content_encoding: Option<&str>,
-    ranges: &Vec<(Option<usize>, Option<usize>)>,
+    ranges: &[(Option<u64>, Option<u64>)],
 ) -> Option<Result<BoxedResponse, hyper::http::Error>> {
-    let size = match get_size(filepath).await {
-        Some(s) => s,
+    let metadata = match get_metadata(filepath).await {
+        Some(m) => m,
         _ => return None,
     };
+    let size = metadata.len();
+
+    let etag = get_etag(&metadata, content_encoding);
+    let last_modified = get_last_modified(&metadata);
+    let content_location = get_content_location(requirements, filepath);
+
+    if is_not_modified(req, &etag, &last_modified) {
+        return Some(build_not_modified_response(
+            &etag,
+            &last_modified,
+            &content_location,
+        ));
+    }
+
+    // a stale If-Range validator yields the full representation
+    if !if_range_is_satisfied(req, &etag, &last_modified) {
+        return compose_get_response(
+            req,
+            requirements,
+            filepath,
+            content_type,
+            StatusCode::OK,
+            content_encoding,
+        )
+        .await;
+    }
 
-    let (start, end) = match get_start_end(ranges, size) {
-        Some(se) => se,
-        _ => {
-            return Some(build_last_resort_response(
-                StatusCode::RANGE_NOT_SATISFIABLE,
-                RANGE_NOT_SATISFIABLE_416,
-            ))
+    // unsatisfiable ranges are dropped from multi range requests
+    let mut start_ends = Vec::with_capacity(ranges.len());
+    for range in ranges {
+        if let Some(se) = get_start_end(range, size) {
+            start_ends.push(se);
         }
-    };
+    }
+
+    let start_ends = coalesce_ranges(start_ends);
+    if start_ends.is_empty() {
+        return Some(build_range_not_satisfiable_response(size));
+    }
+
+    if let [(start, end)] = start_ends.as_slice() {
+        return compose_single_range_response(
+            filepath,
+            &metadata,
+            content_type,
+            content_encoding,
+            content_location,
+            *start,
+            *end,
+        )
+        .await;
+    }
+
+    // range limits only cap multipart responses, a request over them
+    // is served in full like a server without range support would
+    // https://www.rfc-editor.org/rfc/rfc9110#section-14.2
+    if exceeds_range_limits(&start_ends, &requirements.range_limits) {
+        return compose_get_response(
+            req,
+            requirements,
+            filepath,
+            content_type,
+            StatusCode::OK,
+            content_encoding,
+        )
+        .await;
+    }
+
+    Some(compose_multipart_response(
+        filepath,
+        &metadata,
+        content_type,
+        content_encoding,
+        content_location,
+        start_ends,
+    ))
+}
+
+async fn compose_single_range_response(
+    filepath: &Path,
+    metadata: &Metadata,
+    content_type: &str,
+    content_encoding: Option<&str>,
+    content_location: Option<String>,
+    start: u64,
+    end: u64,
+) -> Option<Result<BoxedResponse, hyper::http::Error>> {
+    let size = metadata.len();
 
     let mut file = match File::open(filepath).await {
         Ok(m) => m,
         _ => return None,
     };
 
-    if let Err(_err) = file.seek(SeekFrom::Start(start.clone() as u64)).await {
+    if let Err(_err) = file.seek(SeekFrom::Start(start)).await {
         return None;
     };
 
-    let mut buffer: Vec<u8> = Vec::with_capacity(end - start);
-    buffer.resize(end - start, 0);
+    // inclusive range
+    let length = end - start + 1;
 
-    let content_range_header = build_content_range_header_str(&start, &end, &size);
-    let reader_stream = ReaderStream::with_capacity(file, size);
+    let content_range_header = build_content_range_header_str(start, end, size);
+    let reader_stream = ReaderStream::with_capacity(file.take(length), STREAM_CAPACITY);
     let stream_body = StreamBody::new(reader_stream.map_ok(Frame::data));
     let boxed_body = stream_body.boxed();
 
@@ -252,16 +383,28 @@ async fn compose_single_range_response(
         .status(StatusCode::PARTIAL_CONTENT)
         .header(CONTENT_TYPE, content_type)
         .header(CONTENT_RANGE, content_range_header)
-        .header(CONTENT_LENGTH, buffer.len().to_string());
+        .header(CONTENT_LENGTH, length);
 
     if let Some(enc) = content_encoding {
         builder = builder.header(CONTENT_ENCODING, enc);
     }
 
-    return Some(builder.body(boxed_body));
+    if let Some(et) = get_etag(metadata, content_encoding) {
+        builder = builder.header(ETAG, et);
+    }
+
+    if let Some(lm) = get_last_modified(metadata) {
+        builder = builder.header(LAST_MODIFIED, format_http_date(&lm));
+    }
+
+    if let Some(cl) = content_location {
+        builder = builder.header(CONTENT_LOCATION, cl);
+    }
+
+    Some(builder.body(boxed_body))
 }
 
-async fn get_size(filepath: &PathBuf) -> Option<usize> {
+async fn get_metadata(filepath: &Path) -> Option<Metadata> {
     let metadata = match fs::metadata(filepath).await {
         Ok(m) => m,
         _ => return None,
@@ -271,26 +414,238 @@ async fn get_size(filepath: &PathBuf) -> Option<usize> {
         return None;
     }
 
-    Some(metadata.len() as usize)
+    Some(metadata)
+}
+
+// returns an inclusive (start, end) pair clamped to the file size
+fn exceeds_range_limits(start_ends: &[(u64, u64)], range_limits: &RangeLimits) -> bool {
+    if range_limits.max_ranges < start_ends.len() {
+        return true;
+    }
+
+    let mut total_bytes: u64 = 0;
+    for (start, end) in start_ends {
+        total_bytes = total_bytes.saturating_add(end - start + 1);
+    }
+
+    range_limits.max_bytes < total_bytes
 }
 
-fn get_start_end(
-    ranges: &Vec<(Option<usize>, Option<usize>)>,
-    size: usize,
-) -> Option<(usize, usize)> {
-    let (start, end) = match ranges.get(0) {
-        // suffix (S - N, S)
-        Some((None, Some(end))) => (size - end, size),
-        // prefix (N, S)
-        Some((Some(start), None)) => (start.clone(), size),
-        // windowed (N, M)
-        Some((Some(start), Some(end))) => (start.clone(), end.clone()),
+fn get_start_end(range: &(Option<u64>, Option<u64>), size: u64) -> Option<(u64, u64)> {
+    if 0 == size {
+        return None;
+    }
+
+    let last = size - 1;
+    let (start, end) = match range {
+        // suffix (S - N, S - 1)
+        (None, Some(suffix)) => match suffix {
+            0 => return None,
+            _ => (size.saturating_sub(*suffix), last),
+        },
+        // prefix (N, S - 1)
+        (Some(start), None) => (*start, last),
+        // windowed (N, min(M, S - 1))
+        (Some(start), Some(end)) => (*start, *end.min(&last)),
         _ => return None,
     };
 
-    if start <= end && end <= size {
+    if start <= end && start < size {
         return Some((start, end));
     }
 
     None
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::Bytes;
+    use std::env;
+    use std::path::PathBuf;
+    use std::process;
+    use tokio::fs;
+
+    use crate::available_encodings::AvailableEncodings;
+    use crate::compression::{encode_file, Quality};
+    use crate::get_response::build_get_response;
+
+    const BODY: &str = "0123456789";
+
+    async fn get_test_dir(name: &str) -> PathBuf {
+        let test_dir = env::temp_dir().join(format!("response_{}_{}", name, process::id()));
+        let _ = fs::remove_dir_all(&test_dir).await;
+        fs::create_dir_all(&test_dir).await.unwrap();
+        fs::write(test_dir.join("digits.txt"), BODY).await.unwrap();
+        test_dir
+    }
+
+    fn get_requirements(directory: PathBuf) -> ServiceRequirements {
+        ServiceRequirements {
+            directory,
+            available_encodings: AvailableEncodings::default(),
+            fallback_404: None,
+            range_limits: RangeLimits {
+                max_ranges: 2,
+                max_bytes: 4,
+            },
+            content_location: false,
+            decompress_fallback: false,
+            skip_stale_encodings: false,
+            use_as_dictionary: None,
+            compression_cache: None,
+            client_rules: Vec::new(),
+        }
+    }
+
+    async fn get_body(
+        req: &Request<()>,
+        requirements: &ServiceRequirements,
+    ) -> (StatusCode, Bytes) {
+        let res = build_get_response(req, requirements).await.unwrap();
+        let status = res.status();
+        let body = res.into_body().collect().await.unwrap().to_bytes();
+
+        (status, body)
+    }
+
+    async fn get_status(requirements: &ServiceRequirements, range: &str) -> StatusCode {
+        let req = Request::builder()
+            .uri("/digits.txt")
+            .header(RANGE, range)
+            .body(())
+            .unwrap();
+
+        match build_range_response(&req, requirements).await {
+            Some(Ok(res)) => res.status(),
+            _ => panic!("no range response for {}", range),
+        }
+    }
+
+    #[test]
+    fn parses_ranges() {
+        assert_eq!(
+            get_ranges("bytes=0-4, 6-, -3"),
+            Some(vec![(Some(0), Some(4)), (Some(6), None), (None, Some(3))])
+        );
+        assert_eq!(get_ranges(" bytes=5-5 "), Some(vec![(Some(5), Some(5))]));
+    }
+
+    #[test]
+    fn rejects_malformed_ranges() {
+        assert_eq!(get_ranges("items=0-4"), None);
+        assert_eq!(get_ranges("bytes=4-0"), None);
+        assert_eq!(get_ranges("bytes=a-"), None);
+        assert_eq!(get_ranges("bytes=0-1-2"), None);
+        assert_eq!(get_ranges("bytes=0-1,"), None);
+        assert_eq!(get_ranges("bytes=-"), None);
+    }
+
+    #[test]
+    fn resolves_ranges_against_the_size() {
+        assert_eq!(get_start_end(&(Some(2), Some(4)), 10), Some((2, 4)));
+        assert_eq!(get_start_end(&(Some(2), Some(40)), 10), Some((2, 9)));
+        assert_eq!(get_start_end(&(Some(2), None), 10), Some((2, 9)));
+        assert_eq!(get_start_end(&(None, Some(3)), 10), Some((7, 9)));
+        assert_eq!(get_start_end(&(None, Some(30)), 10), Some((0, 9)));
+    }
+
+    #[test]
+    fn drops_unsatisfiable_ranges() {
+        assert_eq!(get_start_end(&(Some(10), None), 10), None);
+        assert_eq!(get_start_end(&(Some(10), Some(12)), 10), None);
+        assert_eq!(get_start_end(&(None, Some(0)), 10), None);
+        assert_eq!(get_start_end(&(Some(0), None), 0), None);
+    }
+
+    #[tokio::test]
+    async fn serves_a_single_range_over_the_limits() {
+        let test_dir = get_test_dir("single_range").await;
+        let requirements = get_requirements(test_dir.clone());
+
+        assert_eq!(
+            get_status(&requirements, "bytes=0-").await,
+            StatusCode::PARTIAL_CONTENT
+        );
+
+        // coalesced into one range
+        assert_eq!(
+            get_status(&requirements, "bytes=0-3, 2-8, 9-").await,
+            StatusCode::PARTIAL_CONTENT
+        );
+
+        let _ = fs::remove_dir_all(&test_dir).await;
+    }
+
+    #[tokio::test]
+    async fn serves_multiple_ranges_over_the_limits_in_full() {
+        let test_dir = get_test_dir("multiple_ranges").await;
+        let requirements = get_requirements(test_dir.clone());
+
+        assert_eq!(
+            get_status(&requirements, "bytes=0-1, 4-5").await,
+            StatusCode::PARTIAL_CONTENT
+        );
+        assert_eq!(
+            get_status(&requirements, "bytes=0-2, 4-6").await,
+            StatusCode::OK
+        );
+        assert_eq!(
+            get_status(&requirements, "bytes=0-0, 2-2, 4-4").await,
+            StatusCode::OK
+        );
+        assert_eq!(
+            get_status(&requirements, "bytes=20-, 30-").await,
+            StatusCode::RANGE_NOT_SATISFIABLE
+        );
+
+        let _ = fs::remove_dir_all(&test_dir).await;
+    }
+
+    #[tokio::test]
+    async fn falls_back_to_a_regular_get_without_the_identity_file() {
+        let test_dir = get_test_dir("encoded_only").await;
+        let filepath = test_dir.join("digits.txt");
+        encode_file(
+            "gzip",
+            Quality::Fast,
+            &filepath,
+            &test_dir.join("digits.txt.gz"),
+        )
+        .await
+        .unwrap();
+        fs::remove_file(&filepath).await.unwrap();
+        fs::write(test_dir.join("404.txt"), "missing")
+            .await
+            .unwrap();
+
+        let mut requirements = get_requirements(test_dir.clone());
+        requirements.available_encodings =
+            AvailableEncodings::new(&Some(vec!["gzip".to_string()]), &None);
+        requirements.decompress_fallback = true;
+        requirements.fallback_404 = Some(test_dir.join("404.txt"));
+
+        // decompressed responses ignore ranges
+        let req = Request::builder()
+            .uri("/digits.txt")
+            .header(RANGE, "bytes=0-3")
+            .body(())
+            .unwrap();
+        assert_eq!(
+            get_body(&req, &requirements).await,
+            (StatusCode::OK, Bytes::from(BODY))
+        );
+
+        let req = Request::builder()
+            .uri("/missing.txt")
+            .header(RANGE, "bytes=0-3")
+            .body(())
+            .unwrap();
+        assert_eq!(
+            get_body(&req, &requirements).await,
+            (StatusCode::NOT_FOUND, Bytes::from("missing"))
+        );
+
+        let _ = fs::remove_dir_all(&test_dir).await;
+    }
+}
@@ -1,25 +1,28 @@
-use hyper::body::Incoming;
 use hyper::header::ACCEPT_ENCODING;
 use hyper::http::Request;
 use std::ffi::OsStr;
 use std::path;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use tokio::fs;
 
-use crate::available_encodings::{get_encoded_ext, AvailableEncodings};
+use crate::accept_encoding::{get_encoding_qvalue, get_weighted_encodings};
+use crate::available_encodings::{is_dictionary_encoding, AvailableEncodings};
+use crate::decompression::has_decoder;
+use crate::service_requirements::ServiceRequirements;
 
 pub async fn get_path_from_request_url(
-    req: &Request<Incoming>,
-    directory: &PathBuf,
+    req: &Request<()>,
+    requirements: &ServiceRequirements,
 ) -> Option<PathBuf> {
-    let uri_path = req.uri().path();
+    let directory = &requirements.directory;
+    let uri_path = get_normalized_path(req.uri().path());
 
     let stripped = match uri_path.strip_prefix("/") {
         Some(p) => p,
-        _ => uri_path,
+        _ => &uri_path,
     };
 
-    let mut target_path = match path::absolute(directory.join(&stripped)) {
+    let mut target_path = match path::absolute(directory.join(stripped)) {
         Ok(pb) => pb,
         _ => return None,
     };
@@ -29,78 +32,207 @@ pub async fn get_path_from_request_url(
         return None;
     }
 
-    let mtdt = match fs::metadata(&target_path).await {
-        Ok(sdf) => sdf,
-        _ => return None,
-    };
+    match fs::metadata(&target_path).await {
+        // if file bail early
+        Ok(mtdt) if mtdt.is_file() => return Some(target_path),
+        // if directory try an index.html file
+        Ok(mtdt) if mtdt.is_dir() => target_path.push("index.html"),
+        _ => {}
+    }
 
-    // if file bail early
-    if mtdt.is_file() {
+    if path_is_file(&target_path).await {
         return Some(target_path);
     }
 
-    // if directory try an index.html file
-    if mtdt.is_dir() {
-        target_path.push("index.html");
+    // precompressed files may be deployed without an unencoded original
+    if requirements.decompress_fallback
+        && has_encoded_sibling(&target_path, &requirements.available_encodings).await
+    {
+        return Some(target_path);
     }
 
-    let mtdt = match fs::metadata(&target_path).await {
-        Ok(sdf) => sdf,
-        _ => return None,
-    };
+    None
+}
 
-    if mtdt.is_file() {
-        return Some(target_path);
+// Resolves "." and ".." segments so a path never leaves the directory.
+// ".." at the root stays at the root.
+// https://www.rfc-editor.org/rfc/rfc3986#section-5.2.4
+pub fn get_normalized_path(uri_path: &str) -> String {
+    let mut segments: Vec<&str> = Vec::new();
+    for segment in uri_path.split("/") {
+        match segment {
+            "" | "." => {}
+            ".." => {
+                segments.pop();
+            }
+            _ => segments.push(segment),
+        }
+    }
+
+    "/".to_string() + &segments.join("/")
+}
+
+// clients that accept the encoding are served any encoded sibling
+async fn has_encoded_sibling(filepath: &Path, available_encodings: &AvailableEncodings) -> bool {
+    for encoding in available_encodings.get_encodings() {
+        for encoded_path in get_encoded_paths(filepath, available_encodings, encoding) {
+            if path_is_file(&encoded_path).await {
+                return true;
+            }
+        }
+    }
+
+    false
+}
+
+// first encoded sibling that can be decompressed in content_encodings order
+pub async fn get_encoded_sibling(
+    filepath: &Path,
+    available_encodings: &AvailableEncodings,
+) -> Option<(PathBuf, String)> {
+    for encoding in available_encodings.get_encodings() {
+        if !has_decoder(encoding) {
+            continue;
+        }
+
+        for encoded_path in get_encoded_paths(filepath, available_encodings, encoding) {
+            if path_is_file(&encoded_path).await {
+                return Some((encoded_path, encoding.to_string()));
+            }
+        }
     }
 
     None
 }
 
-pub fn get_encodings(
-    req: &Request<Incoming>,
-    content_encodings: &Option<Vec<String>>,
-) -> Option<Vec<String>> {
-    let accept_encoding_header = match req.headers().get(ACCEPT_ENCODING) {
-        Some(enc) => enc,
-        _ => return None,
-    };
+// encoded siblings in suffix order, dictionary encodings have none
+pub fn get_encoded_paths(
+    filepath: &Path,
+    available_encodings: &AvailableEncodings,
+    encoding: &str,
+) -> Vec<PathBuf> {
+    if is_dictionary_encoding(encoding) {
+        return Vec::new();
+    }
 
-    let encoding_str = match accept_encoding_header.to_str() {
-        Ok(s) => s,
-        _ => return None,
-    };
+    available_encodings
+        .get_extensions(encoding)
+        .iter()
+        .map(|ext| add_extension(filepath, ext))
+        .collect()
+}
 
-    let available_encodings = AvailableEncodings::new(content_encodings);
+async fn path_is_file(filepath: &Path) -> bool {
+    match fs::metadata(filepath).await {
+        Ok(mtdt) => mtdt.is_file(),
+        _ => false,
+    }
+}
 
-    let mut encodings = Vec::new();
-    for encoding in encoding_str.split(",") {
-        let trimmed = encoding.trim();
-        if available_encodings.encoding_is_available(trimmed) {
-            encodings.push(trimmed.to_string());
+// Acceptable encodings are ordered by client q-value first and then by
+// the server preference order of content_encodings.
+pub fn get_encodings(
+    req: &Request<()>,
+    available_encodings: &AvailableEncodings,
+) -> Option<Vec<String>> {
+    let weighted_encodings = get_weighted_encodings_from_request(req)?;
+    let wildcard_qvalue = get_encoding_qvalue(&weighted_encodings, "*");
+
+    let mut encodings: Vec<(String, u16)> = Vec::new();
+    for encoding in available_encodings.get_encodings() {
+        let qvalue = match get_encoding_qvalue(&weighted_encodings, encoding) {
+            Some(q) => q,
+            _ => wildcard_qvalue.unwrap_or(0),
+        };
+
+        if 0 < qvalue {
+            encodings.push((encoding.to_string(), qvalue));
         }
     }
 
-    if 0 < encodings.len() {
-        return Some(encodings);
+    // stable sort keeps server preference for equal q-values
+    encodings.sort_by(|(_, a), (_, b)| b.cmp(a));
+
+    if !encodings.is_empty() {
+        return Some(encodings.into_iter().map(|(enc, _)| enc).collect());
     }
 
     None
 }
 
+// https://www.rfc-editor.org/rfc/rfc9110#section-12.5.3
+pub fn identity_is_acceptable(req: &Request<()>) -> bool {
+    let weighted_encodings = match get_weighted_encodings_from_request(req) {
+        Some(we) => we,
+        _ => return true,
+    };
+
+    if let Some(qvalue) = get_encoding_qvalue(&weighted_encodings, "identity") {
+        return 0 < qvalue;
+    }
+
+    match get_encoding_qvalue(&weighted_encodings, "*") {
+        Some(qvalue) => 0 < qvalue,
+        _ => true,
+    }
+}
+
+fn get_weighted_encodings_from_request(req: &Request<()>) -> Option<Vec<(String, u16)>> {
+    let accept_encoding_header = match req.headers().get(ACCEPT_ENCODING) {
+        Some(enc) => enc,
+        _ => return None,
+    };
+
+    match accept_encoding_header.to_str() {
+        Ok(s) => Some(get_weighted_encodings(s)),
+        _ => None,
+    }
+}
+
 // nightly API replacement
 // https://doc.rust-lang.org/std/path/struct.Path.html#method.with_added_extension
 
 // Filepath must be an file, not a directory for this to work.
-pub fn add_extension(filepath: &PathBuf, encoding: &str) -> Option<PathBuf> {
-    let enc_ext = match get_encoded_ext(encoding) {
-        Some(enc) => enc,
+pub fn add_extension(filepath: &Path, extension: &str) -> PathBuf {
+    let mut fp_with_ext = filepath.as_os_str().to_os_string();
+    fp_with_ext.push(OsStr::new(extension));
+
+    PathBuf::from(fp_with_ext)
+}
+
+// https://www.rfc-editor.org/rfc/rfc9110#name-content-location
+pub fn get_content_location(requirements: &ServiceRequirements, filepath: &Path) -> Option<String> {
+    if !requirements.content_location {
+        return None;
+    }
+
+    let relative_path = match filepath.strip_prefix(&requirements.directory) {
+        Ok(rp) => rp,
         _ => return None,
     };
 
-    let os_ext = OsStr::new(enc_ext);
+    let mut content_location = String::new();
+    for component in relative_path.components() {
+        let segment = match component.as_os_str().to_str() {
+            Some(s) => s,
+            _ => return None,
+        };
 
-    let mut fp_with_ext = filepath.as_os_str().to_os_string();
-    fp_with_ext.push(os_ext);
+        content_location.push('/');
+        push_percent_encoded(&mut content_location, segment);
+    }
 
-    Some(PathBuf::from(fp_with_ext))
+    Some(content_location)
+}
+
+// https://www.rfc-editor.org/rfc/rfc3986#section-3.3
+fn push_percent_encoded(target: &mut String, segment: &str) {
+    for byte in segment.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' => target.push(byte as char),
+            b'-' | b'.' | b'_' | b'~' | b'!' | b'$' | b'&' | b'\'' | b'(' | b')' | b'*' | b'+'
+            | b',' | b';' | b'=' | b':' | b'@' => target.push(byte as char),
+            _ => target.push_str(&format!("%{:02X}", byte)),
+        }
+    }
 }
@@ -1,25 +1,96 @@
-use hyper::body::Incoming;
-use hyper::http::Request;
+use http_body_util::{BodyExt, Empty};
+use hyper::header::{HeaderName, HeaderValue, ACCEPT_ENCODING, VARY};
+use hyper::http::{Request, Response};
 use hyper::Method;
 use hyper::StatusCode;
-use std::path::PathBuf;
 
+use crate::client_authorization::client_is_authorized;
+use crate::dictionary::{get_use_as_dictionary, AVAILABLE_DICTIONARY, USE_AS_DICTIONARY};
 use crate::get_response::build_get_response;
-use crate::head_response::build_head_response;
-use crate::last_resort_response::build_last_resort_response;
+use crate::last_resort_response::{build_last_resort_response, FORBIDDEN_403};
+use crate::service_requirements::ServiceRequirements;
 use crate::type_flyweight::BoxedResponse;
 
 pub const METHOD_NOT_ALLOWED_405: &str = "405 method not allowed";
 
-pub async fn build_response(
-    req: Request<Incoming>,
-    directory: PathBuf,
-    content_encodings: Option<Vec<String>>,
-    fallback_404: Option<PathBuf>,
+// Request bodies are never read so any body type is accepted. This lets
+// HTTP/1.1, HTTP/2, and HTTP/3 requests share one pipeline.
+pub async fn build_response<B>(
+    req: Request<B>,
+    requirements: ServiceRequirements,
 ) -> Result<BoxedResponse, hyper::http::Error> {
-    match req.method() {
-        &Method::HEAD => build_head_response(req, directory, content_encodings).await,
-        &Method::GET => build_get_response(req, directory, content_encodings, fallback_404).await,
+    let (parts, _body) = req.into_parts();
+    let req = Request::from_parts(parts, ());
+
+    // client certificates are checked before any path is resolved
+    if !client_is_authorized(&req, &requirements.client_rules) {
+        return build_last_resort_response(StatusCode::FORBIDDEN, FORBIDDEN_403);
+    }
+
+    // HEAD responses are GET responses rendered without a body
+    match *req.method() {
+        Method::HEAD => build_get_response(&req, &requirements)
+            .await
+            .map(|res| add_vary_headers(res, &requirements))
+            .map(|res| add_use_as_dictionary_header(res, &req, &requirements))
+            .map(strip_body),
+        Method::GET => build_get_response(&req, &requirements)
+            .await
+            .map(|res| add_vary_headers(res, &requirements))
+            .map(|res| add_use_as_dictionary_header(res, &req, &requirements)),
         _ => build_last_resort_response(StatusCode::METHOD_NOT_ALLOWED, METHOD_NOT_ALLOWED_405),
     }
 }
+
+// request headers that select between representations of a file
+fn get_vary_headers(requirements: &ServiceRequirements) -> Vec<HeaderName> {
+    let mut vary_headers = Vec::new();
+
+    let available_encodings = &requirements.available_encodings;
+    if available_encodings.any_available() {
+        vary_headers.push(ACCEPT_ENCODING);
+    }
+
+    if available_encodings.any_dictionary_available() {
+        vary_headers.push(AVAILABLE_DICTIONARY);
+    }
+
+    vary_headers
+}
+
+fn add_vary_headers(mut res: BoxedResponse, requirements: &ServiceRequirements) -> BoxedResponse {
+    for header_name in get_vary_headers(requirements) {
+        res.headers_mut()
+            .append(VARY, HeaderValue::from_name(header_name));
+    }
+
+    res
+}
+
+// successful responses on configured paths may be used as dictionaries
+// for later versions of themselves
+fn add_use_as_dictionary_header(
+    mut res: BoxedResponse,
+    req: &Request<()>,
+    requirements: &ServiceRequirements,
+) -> BoxedResponse {
+    if StatusCode::OK != res.status() {
+        return res;
+    }
+
+    let use_as_dictionary = match get_use_as_dictionary(req, &requirements.use_as_dictionary) {
+        Some(uad) => uad,
+        _ => return res,
+    };
+
+    if let Ok(header_value) = HeaderValue::from_str(&use_as_dictionary) {
+        res.headers_mut().insert(USE_AS_DICTIONARY, header_value);
+    }
+
+    res
+}
+
+fn strip_body(res: BoxedResponse) -> BoxedResponse {
+    let (parts, _body) = res.into_parts();
+    Response::from_parts(parts, Empty::new().map_err(|e| match e {}).boxed())
+}
@@ -0,0 +1,20 @@
+use std::path::PathBuf;
+
+use crate::available_encodings::AvailableEncodings;
+use crate::client_authorization::ClientRule;
+use crate::compression_cache::CompressionCache;
+use crate::range_response::RangeLimits;
+
+#[derive(Clone, Debug)]
+pub struct ServiceRequirements {
+    pub directory: PathBuf,
+    pub available_encodings: AvailableEncodings,
+    pub fallback_404: Option<PathBuf>,
+    pub range_limits: RangeLimits,
+    pub content_location: bool,
+    pub decompress_fallback: bool,
+    pub skip_stale_encodings: bool,
+    pub use_as_dictionary: Option<Vec<String>>,
+    pub compression_cache: Option<CompressionCache>,
+    pub client_rules: Vec<ClientRule>,
+}
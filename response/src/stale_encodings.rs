@@ -0,0 +1,54 @@
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use tokio::fs;
+
+static STALE_ENCODINGS_SKIPPED: AtomicU64 = AtomicU64::new(0);
+// stale encoded files already logged
+static LOGGED: Mutex<BTreeSet<PathBuf>> = Mutex::new(BTreeSet::new());
+
+// number of times a stale encoded file was skipped since startup
+pub fn stale_encodings_skipped() -> u64 {
+    STALE_ENCODINGS_SKIPPED.load(Ordering::Relaxed)
+}
+
+// An encoded file is stale when its unencoded original was modified
+// after it. Encoded files without an original are never stale.
+pub async fn skip_stale_encoded_file(filepath: &Path, encoded_path: &Path) -> bool {
+    let original_mtime = match fs::metadata(filepath).await {
+        Ok(m) => match m.modified() {
+            Ok(mtime) => mtime,
+            _ => return false,
+        },
+        _ => return false,
+    };
+
+    let encoded_mtime = match fs::metadata(encoded_path).await {
+        Ok(m) => match m.modified() {
+            Ok(mtime) => mtime,
+            _ => return false,
+        },
+        _ => return false,
+    };
+
+    if encoded_mtime < original_mtime {
+        STALE_ENCODINGS_SKIPPED.fetch_add(1, Ordering::Relaxed);
+        log_stale_encoded_file(encoded_path);
+        return true;
+    }
+
+    false
+}
+
+// every request for a stale file skips it, only the first one is logged
+fn log_stale_encoded_file(encoded_path: &Path) {
+    if let Ok(mut logged) = LOGGED.lock() {
+        if logged.insert(encoded_path.to_path_buf()) {
+            println!(
+                "file_server: skipping stale encoded file {}",
+                encoded_path.display()
+            );
+        }
+    }
+}
@@ -0,0 +1,81 @@
+// "*" matches any run of bytes, everything else matches itself
+pub fn wildcard_matches(pattern: &[u8], value: &[u8]) -> bool {
+    let (mut pattern_index, mut value_index) = (0, 0);
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while value_index < value.len() {
+        if pattern_index < pattern.len() && b'*' == pattern[pattern_index] {
+            backtrack = Some((pattern_index, value_index));
+            pattern_index += 1;
+            continue;
+        }
+
+        if pattern_index < pattern.len() && pattern[pattern_index] == value[value_index] {
+            pattern_index += 1;
+            value_index += 1;
+            continue;
+        }
+
+        // let the last wildcard consume one more byte
+        match backtrack {
+            Some((pi, vi)) => {
+                backtrack = Some((pi, vi + 1));
+                pattern_index = pi + 1;
+                value_index = vi + 1;
+            }
+            _ => return false,
+        }
+    }
+
+    pattern[pattern_index..].iter().all(|b| b'*' == *b)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(pattern: &str, value: &str) -> bool {
+        wildcard_matches(pattern.as_bytes(), value.as_bytes())
+    }
+
+    #[test]
+    fn matches_literals_exactly() {
+        assert!(matches("ci-1.example.com", "ci-1.example.com"));
+        assert!(!matches("ci-1.example.com", "ci-1.example.co"));
+        assert!(!matches("ci-1.example.co", "ci-1.example.com"));
+    }
+
+    #[test]
+    fn matches_a_wildcard_at_the_end() {
+        assert!(matches("ci-*", "ci-1"));
+        assert!(matches("ci-*", "ci-"));
+        assert!(!matches("ci-*", "ci"));
+        assert!(!matches("ci-*", "laptop"));
+    }
+
+    #[test]
+    fn matches_a_wildcard_in_the_middle() {
+        assert!(matches("ci-*.example.com", "ci-1.example.com"));
+        assert!(matches("ci-*.example.com", "ci-.example.com"));
+        assert!(matches("ci-*.example.com", "ci-a.b.example.com"));
+        assert!(!matches("ci-*.example.com", "ci-1.example.org"));
+        assert!(matches("/assets/*/*.css", "/assets/v1/site.css"));
+        assert!(!matches("/assets/*/*.css", "/assets/site.js"));
+    }
+
+    #[test]
+    fn backtracks_past_repeated_characters() {
+        assert!(matches("*aab", "aaab"));
+        assert!(matches("a*b*c", "abbbcbc"));
+        assert!(!matches("a*b*c", "abbbcb"));
+    }
+
+    #[test]
+    fn matches_empty_patterns_and_values() {
+        assert!(matches("", ""));
+        assert!(!matches("", "ci-1"));
+        assert!(matches("*", ""));
+        assert!(matches("**", "anything"));
+        assert!(!matches("ci-*", ""));
+    }
+}